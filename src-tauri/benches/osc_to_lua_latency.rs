@@ -0,0 +1,88 @@
+//! Time from `LuaEngineEvent::OscReceived` to the `osc.send` it causes.
+//!
+//! ```shell
+//! cargo bench --bench osc_to_lua_latency
+//! ```
+//!
+//! `polling` drives the engine the way the app used to (`process_event` and a 1 ms sleep when
+//! idle), `channel` uses `LuaEngine::run`, which wakes up on each event.
+
+use osc_wardrobe_lib::application_event::ApplicationEvent;
+use osc_wardrobe_lib::lua::{LuaEngine, LuaEngineEvent, LuaEngineOption};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const MAIN_LUA: &str = "function receive(addr, args) osc.send(addr, args[1]) end\n";
+const ITERATIONS: usize = 1000;
+
+fn measure(polling: bool) -> Vec<Duration> {
+    let base_dir = std::env::temp_dir().join("osc-wardrobe-bench");
+    std::fs::create_dir_all(&base_dir).unwrap();
+    std::fs::write(base_dir.join("main.lua"), MAIN_LUA).unwrap();
+
+    let (lua_sender, lua_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (event_sender, mut event_receiver) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let mut engine = LuaEngine::new(LuaEngineOption {
+                io_dir: base_dir.join("io"),
+                base_dir,
+                lua_engine_event_receiver: lua_receiver,
+                application_event_sender: Arc::new(event_sender),
+                print_sender: None,
+                settings: Default::default(),
+                osc_filter: Default::default(),
+                osc_cache: Default::default(),
+            });
+            engine.start().await.unwrap();
+            if polling {
+                loop {
+                    if engine.process_event().await == 0 {
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                    }
+                }
+            } else {
+                engine.run().await;
+            }
+        });
+    });
+
+    let mut latencies = Vec::with_capacity(ITERATIONS);
+    for i in 0..ITERATIONS {
+        let start = Instant::now();
+        lua_sender
+            .send(LuaEngineEvent::OscReceived(
+                "/bench".to_string(),
+                vec![rosc::OscType::Int(i as i32)],
+            ))
+            .unwrap();
+        match event_receiver.blocking_recv() {
+            Some(ApplicationEvent::SendOsc(..)) => latencies.push(start.elapsed()),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+    latencies
+}
+
+fn report(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let len = latencies.len();
+    let mean = latencies.iter().sum::<Duration>() / len as u32;
+    println!(
+        "{}: mean {:.3?}, p50 {:.3?}, p99 {:.3?}, max {:.3?}",
+        name,
+        mean,
+        latencies[len / 2],
+        latencies[len * 99 / 100],
+        latencies[len - 1]
+    );
+}
+
+fn main() {
+    report("polling", measure(true));
+    report("channel", measure(false));
+}
@@ -83,13 +83,45 @@ local function find(conditions, parameters)
     return nil
 end
 
+-- parameter addresses in conditions, e.g. "/avatar/parameters/Outfit" of "/avatar/parameters/Outfit=1"
+local function collect_addresses(conditions, result)
+    for _, t in ipairs(conditions) do
+        for key, _ in pairs(t.cond) do
+            result[key:match("^([^=]*)")] = true
+        end
+        if type(t.v) == "table" then
+            collect_addresses(t.v, result)
+        end
+    end
+    return result
+end
+
 local processor = {
     avatar_context = {
         parameters = {},
         conditions = {},
-    }
+    },
+    -- `osc.on` ids of the parameters of the current avatar
+    subscriptions = {},
 }
 
+-- listens only to parameters the conditions refer to
+local function subscribe(conditions)
+    for _, id in ipairs(processor.subscriptions) do
+        osc.off(id)
+    end
+    processor.subscriptions = {}
+    if not processor.on_parameter then return end
+    for addr, _ in pairs(collect_addresses(conditions, {})) do
+        local id, err = osc.on(addr, processor.on_parameter)
+        if id then
+            table.insert(processor.subscriptions, id)
+        else
+            print("[wardrobe bridge] can't listen to", addr, err)
+        end
+    end
+end
+
 local function onavatarchange(id, keep_parameters)
     local avatar = wardrobe.definition.avatars[id]
     processor.avatar_context = {
@@ -97,6 +129,7 @@ local function onavatarchange(id, keep_parameters)
         parameters = keep_parameters and processor.avatar_context.parameters or {},
         conditions = build_conditions_list(avatar or {})
     }
+    subscribe(processor.avatar_context.conditions)
     if not id then return end
 
     print("[wardrobe bridge] onavatarchange", id)
@@ -105,8 +138,12 @@ local function onavatarchange(id, keep_parameters)
     end
 end
 
-function processor:init()
-    --
+-- `on_parameter(addr, args)` is called for parameters in conditions of the current avatar
+function processor:init(on_parameter)
+    self.on_parameter = on_parameter
+    osc.on("/avatar/change", function(addr, args)
+        self:receive(addr, args)
+    end)
 end
 function processor:on_definition_changed()
     print("[wardrobe bridge] definition changed")
@@ -1,7 +1,26 @@
 local sys = require('init')
 
+-- called with parameters in conditions of the current avatar
+local function on_parameter(addr, args)
+    sys:receive(addr, args)
+    -- print(addr, table.unpack(args))
+    local alias = sys:find_avatar()
+    if alias then
+        local blueprint_id = wardrobe.definition.aliases[alias]
+        if blueprint_id then
+            print("alias found:", alias, "->", blueprint_id)
+            local success, err = osc.send("/avatar/change", blueprint_id)
+            if not success then
+                print("error on avatar change", err)
+            end
+        else
+            print("alias found:", alias, "but blueprint_id not found")
+        end
+    end
+end
+
 local function init()
-    sys:init()
+    sys:init(on_parameter)
     setmetatable(wardrobe, {
         __newindex = function(t, k, v)
             rawset(t, k, v)
@@ -16,24 +35,9 @@ function start()
     init()
 end
 
-function receive(addr, args)
-    sys:receive(addr, args)
-    -- print(addr, table.unpack(args))
-    if addr == "/avatar/change" then
-        -- on avatar change
-    else
-        local alias = sys:find_avatar()
-        if alias then
-            local blueprint_id = wardrobe.definition.aliases[alias]
-            if blueprint_id then
-                print("alias found:", alias, "->", blueprint_id)
-                local success, err = osc.send("/avatar/change", blueprint_id)
-                if not success then
-                    print("error on avatar change", err)
-                end
-            else
-                print("alias found:", alias, "but blueprint_id not found")
-            end
-        end
-    end
+-- called on exit and before reload
+function stop()
 end
+
+-- a global `receive(addr, args)` would get every OSC message; `osc.on` only wakes Lua for
+-- addresses it needs
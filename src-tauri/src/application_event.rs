@@ -1,5 +1,29 @@
+pub use crate::osc_query::{Access, EndpointType, OscEndpoint};
+pub use crate::script_menu::{ScriptMenuItem, ScriptMenuKind};
+
+/// Requests from Lua scripts and the UI to the application.
+#[derive(Debug)]
 pub enum ApplicationEvent {
-    SendOsc(String, serde_json::Value),
+    /// `osc.send(addr, ...)`. args are converted by [`crate::osc_lua::from_lua`]
+    SendOsc(String, Vec<rosc::OscType>),
+    /// `osc.bundle({ {addr, ...}, ... }, time)`, sent as one OSC bundle. time is a
+    /// `{type="time", ...}` timetag or a delay in seconds. `None` is "immediately"
+    SendOscBundle(Vec<(String, Vec<rosc::OscType>)>, Option<rosc::OscTime>),
+    /// `osc.send_to(target, addr, ...)`, target is a name in [`crate::settings::OscSettings::targets`]
+    SendOscTo(String, String, Vec<rosc::OscType>),
+    /// returned `addr, ...` of `on_client_connected(name, addr)`, sent only to that client.
+    /// `(client name, addr, args)`
+    SendOscToClient(String, String, Vec<rosc::OscType>),
+    /// `osc.select_client(name)`: VRChat client to send to, by name prefix.
+    /// `None` is the last connected one, or `osc.client` in settings
+    SelectOscClient(Option<String>),
+    /// every `osc.endpoint` of scripts, after load and whenever it changes
+    SetOscEndpoints(Vec<OscEndpoint>),
+    /// every `wardrobe.menu` item of scripts, after load and whenever it changes
+    SetScriptMenu(Vec<ScriptMenuItem>),
     ReloadLua,
+    /// tray "quit", `wardrobe.exit()` or an exit request of the OS. Runs [`crate::shutdown::Shutdown`]
     Exit,
+    /// Lua has run `stop()` after [`ApplicationEvent::Exit`]; whatever it sent is queued before this
+    LuaStopped,
 }
@@ -0,0 +1,148 @@
+//! Every avatar the wardrobe knows of, so that the UI and scripts can pick one by name instead
+//! of a hand-typed blueprint ID. Saved as `avatar_catalog.json` in AppData.
+
+use crate::avatar_config::AvatarConfig;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct CatalogEntry {
+    /// blueprint ID
+    pub id: String,
+    /// from VRChat's OSC config
+    pub name: Option<String>,
+    /// names in `definition.aliases` pointing to it
+    pub aliases: Vec<String>,
+    /// parameter names from VRChat's OSC config
+    pub parameters: Vec<String>,
+    /// RFC 3339, when VRChat last reported switching to it
+    pub last_seen: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct AvatarCatalog {
+    pub avatars: BTreeMap<String, CatalogEntry>,
+}
+
+impl AvatarCatalog {
+    /// Missing or broken file is an empty catalog.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn entry(&mut self, id: &str) -> &mut CatalogEntry {
+        self.avatars
+            .entry(id.to_string())
+            .or_insert_with(|| CatalogEntry {
+                id: id.to_string(),
+                ..Default::default()
+            })
+    }
+
+    /// VRChat switched to `id`. Always a change.
+    pub fn seen(&mut self, id: &str, now: DateTime<Utc>) -> bool {
+        self.entry(id).last_seen = Some(now.to_rfc3339());
+        true
+    }
+
+    /// Takes names and parameters from VRChat's OSC configs. Returns `true` if anything changed.
+    pub fn merge_configs(&mut self, configs: &BTreeMap<String, AvatarConfig>) -> bool {
+        let before = self.clone();
+        for config in configs.values() {
+            let entry = self.entry(&config.id);
+            entry.name = Some(config.name.clone());
+            entry.parameters = config.parameters.iter().map(|p| p.name.clone()).collect();
+        }
+        *self != before
+    }
+
+    /// Takes `aliases` (`{"alias": "avtr_xxx"}`) of the definition, replacing previous ones.
+    /// Returns `true` if anything changed.
+    pub fn merge_aliases(&mut self, definition: &serde_json::Value) -> bool {
+        let before = self.clone();
+        for entry in self.avatars.values_mut() {
+            entry.aliases.clear();
+        }
+        if let Some(aliases) = definition.get("aliases").and_then(|a| a.as_object()) {
+            for (alias, id) in aliases {
+                if let Some(id) = id.as_str() {
+                    self.entry(id).aliases.push(alias.clone());
+                }
+            }
+        }
+        *self != before
+    }
+}
+
+#[test]
+fn avatar_catalog_test() {
+    let mut catalog = AvatarCatalog::default();
+    let now = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    assert!(catalog.seen("avtr_casual", now));
+    assert_eq!(
+        catalog.avatars["avtr_casual"].last_seen.as_deref(),
+        Some("2025-01-01T00:00:00+00:00")
+    );
+
+    let configs = BTreeMap::from([(
+        "avtr_casual".to_string(),
+        AvatarConfig {
+            id: "avtr_casual".to_string(),
+            name: "Casual".to_string(),
+            parameters: vec![crate::avatar_config::AvatarParameter {
+                name: "Outfit".to_string(),
+                input: None,
+                output: None,
+            }],
+        },
+    )]);
+    assert!(catalog.merge_configs(&configs));
+    assert!(!catalog.merge_configs(&configs), "nothing new");
+    assert_eq!(
+        catalog.avatars["avtr_casual"].name.as_deref(),
+        Some("Casual")
+    );
+    assert_eq!(catalog.avatars["avtr_casual"].parameters, vec!["Outfit"]);
+
+    let definition = serde_json::json!({
+        "aliases": {"casual": "avtr_casual", "formal": "avtr_formal"}
+    });
+    assert!(catalog.merge_aliases(&definition));
+    assert!(!catalog.merge_aliases(&definition), "nothing new");
+    assert_eq!(catalog.avatars["avtr_casual"].aliases, vec!["casual"]);
+    assert_eq!(catalog.avatars["avtr_formal"].aliases, vec!["formal"]);
+    assert_eq!(catalog.avatars["avtr_formal"].last_seen, None);
+
+    assert!(catalog.merge_aliases(&serde_json::json!({"aliases": {}})));
+    assert!(
+        catalog.avatars["avtr_formal"].aliases.is_empty(),
+        "removed alias, but the avatar stays"
+    );
+
+    let path = std::env::temp_dir()
+        .join("osc-wardrobe-test")
+        .join("avatar_catalog.json");
+    catalog.save(&path).unwrap();
+    assert_eq!(AvatarCatalog::load(&path), catalog);
+    assert_eq!(
+        AvatarCatalog::load(&path.with_file_name("missing.json")),
+        AvatarCatalog::default()
+    );
+}
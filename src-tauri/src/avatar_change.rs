@@ -0,0 +1,270 @@
+//! Guard for outgoing `/avatar/change`: VRChat reloads the avatar on each one and throttles
+//! frequent changes, so scripts matching a condition on every message must not spam them.
+
+use log::info;
+use std::time::{Duration, Instant};
+
+/// What to do with a requested avatar change.
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Send,
+    /// sent by [`AvatarChangeGuard::poll`] when the cooldown ends, unless replaced by a later
+    /// request. `replaced` is the pending target dropped for this one
+    Defer {
+        until: Instant,
+        replaced: Option<String>,
+    },
+    /// dropped for `reason`. `cancelled` is the pending target dropped with it
+    Suppress {
+        reason: &'static str,
+        cancelled: Option<String>,
+    },
+}
+
+#[derive(Default)]
+pub struct AvatarChangeGuard {
+    /// `(target, when)` of the last change sent
+    last_sent: Option<(String, Instant)>,
+    pending: Option<String>,
+}
+
+impl AvatarChangeGuard {
+    /// `current` is the avatar VRChat reported last.
+    pub fn request(
+        &mut self,
+        id: &str,
+        current: Option<&str>,
+        cooldown: Duration,
+        now: Instant,
+    ) -> Decision {
+        // going back to the current or requested avatar cancels what is waiting
+        if current == Some(id) {
+            return Decision::Suppress {
+                reason: "already the current avatar",
+                cancelled: self.pending.take(),
+            };
+        }
+        match &self.last_sent {
+            Some((last, at)) if now < *at + cooldown => {
+                if last == id {
+                    return Decision::Suppress {
+                        reason: "already requested",
+                        cancelled: self.pending.take(),
+                    };
+                }
+                let replaced = self.pending.replace(id.to_string());
+                Decision::Defer {
+                    until: *at + cooldown,
+                    replaced: replaced.filter(|r| r != id),
+                }
+            }
+            _ => {
+                self.pending = None;
+                self.last_sent = Some((id.to_string(), now));
+                Decision::Send
+            }
+        }
+    }
+
+    /// When to call [`AvatarChangeGuard::poll`], if something is waiting.
+    pub fn deadline(&self, cooldown: Duration) -> Option<Instant> {
+        match (&self.pending, &self.last_sent) {
+            (Some(_), Some((_, at))) => Some(*at + cooldown),
+            _ => None,
+        }
+    }
+
+    /// The waiting target to send now, if the cooldown is over and it is still a change.
+    pub fn poll(
+        &mut self,
+        current: Option<&str>,
+        cooldown: Duration,
+        now: Instant,
+    ) -> Option<String> {
+        if self
+            .deadline(cooldown)
+            .is_none_or(|deadline| now < deadline)
+        {
+            return None;
+        }
+        let id = self.pending.take()?;
+        if current == Some(id.as_str()) {
+            info!(
+                "avatar change to {} suppressed: already the current avatar",
+                id
+            );
+            return None;
+        }
+        self.last_sent = Some((id.clone(), now));
+        Some(id)
+    }
+}
+
+/// Whether VRChat switched to a sent `/avatar/change` target.
+#[derive(Debug, PartialEq)]
+pub struct SwitchResult {
+    pub id: String,
+    pub ok: bool,
+    /// why it failed
+    pub reason: Option<String>,
+}
+
+impl SwitchResult {
+    fn failed(id: String, reason: String) -> SwitchResult {
+        SwitchResult {
+            id,
+            ok: false,
+            reason: Some(reason),
+        }
+    }
+}
+
+/// Waits for VRChat to send back `/avatar/change` with the target. If it does not switch, e.g.
+/// the avatar is not available to the user, nothing comes back and the switch times out.
+#[derive(Default)]
+pub struct SwitchConfirmation {
+    pending: Option<(String, Instant)>,
+}
+
+impl SwitchConfirmation {
+    /// A change to `id` is sent. Returns the failure of the previous one if it is still pending.
+    pub fn sent(&mut self, id: &str, now: Instant) -> Option<SwitchResult> {
+        let (previous, _) = self.pending.replace((id.to_string(), now))?;
+        (previous != id).then(|| SwitchResult::failed(previous, format!("replaced by {}", id)))
+    }
+
+    /// VRChat reports `id` as the current avatar.
+    pub fn received(&mut self, id: &str) -> Option<SwitchResult> {
+        let (pending, _) = self.pending.take()?;
+        Some(if pending == id {
+            SwitchResult {
+                id: pending,
+                ok: true,
+                reason: None,
+            }
+        } else {
+            SwitchResult::failed(pending, format!("switched to {} instead", id))
+        })
+    }
+
+    /// When to call [`SwitchConfirmation::poll`], if a switch is pending.
+    pub fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, at)| *at + timeout)
+    }
+
+    /// The failure of the pending switch, if it timed out.
+    pub fn poll(&mut self, timeout: Duration, now: Instant) -> Option<SwitchResult> {
+        if self.deadline(timeout).is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        let (id, _) = self.pending.take()?;
+        Some(SwitchResult::failed(
+            id,
+            "timed out; the avatar may be unavailable".to_string(),
+        ))
+    }
+}
+
+#[test]
+fn avatar_change_guard_test() {
+    let cooldown = Duration::from_secs(5);
+    let t0 = Instant::now();
+    let at = |s: u64| t0 + Duration::from_secs(s);
+    let mut guard = AvatarChangeGuard::default();
+
+    assert_eq!(
+        guard.request("avtr_a", Some("avtr_a"), cooldown, t0),
+        Decision::Suppress {
+            reason: "already the current avatar",
+            cancelled: None
+        }
+    );
+    assert_eq!(
+        guard.request("avtr_b", Some("avtr_a"), cooldown, t0),
+        Decision::Send
+    );
+    assert_eq!(
+        guard.request("avtr_b", Some("avtr_a"), cooldown, at(1)),
+        Decision::Suppress {
+            reason: "already requested",
+            cancelled: None
+        }
+    );
+
+    // burst within the cooldown: the last one wins
+    assert_eq!(
+        guard.request("avtr_c", Some("avtr_b"), cooldown, at(1)),
+        Decision::Defer {
+            until: at(5),
+            replaced: None
+        }
+    );
+    assert_eq!(
+        guard.request("avtr_d", Some("avtr_b"), cooldown, at(2)),
+        Decision::Defer {
+            until: at(5),
+            replaced: Some("avtr_c".to_string())
+        }
+    );
+    assert_eq!(guard.deadline(cooldown), Some(at(5)));
+    assert_eq!(guard.poll(Some("avtr_b"), cooldown, at(4)), None);
+    assert_eq!(
+        guard.poll(Some("avtr_b"), cooldown, at(5)),
+        Some("avtr_d".to_string())
+    );
+    assert_eq!(guard.deadline(cooldown), None);
+
+    // back to the current avatar cancels the waiting one
+    guard.request("avtr_e", Some("avtr_d"), cooldown, at(6));
+    assert_eq!(
+        guard.request("avtr_d", Some("avtr_d"), cooldown, at(7)),
+        Decision::Suppress {
+            reason: "already the current avatar",
+            cancelled: Some("avtr_e".to_string())
+        }
+    );
+    assert_eq!(guard.poll(Some("avtr_d"), cooldown, at(10)), None);
+
+    assert_eq!(
+        guard.request("avtr_e", Some("avtr_d"), cooldown, at(11)),
+        Decision::Send
+    );
+}
+
+#[test]
+fn switch_confirmation_test() {
+    let timeout = Duration::from_secs(30);
+    let t0 = Instant::now();
+    let at = |s: u64| t0 + Duration::from_secs(s);
+    let mut switches = SwitchConfirmation::default();
+
+    assert_eq!(switches.received("avtr_a"), None, "nothing pending");
+    assert_eq!(switches.sent("avtr_a", t0), None);
+    assert_eq!(
+        switches.received("avtr_a"),
+        Some(SwitchResult {
+            id: "avtr_a".to_string(),
+            ok: true,
+            reason: None
+        })
+    );
+    assert_eq!(switches.deadline(timeout), None);
+
+    switches.sent("avtr_b", at(1));
+    assert_eq!(
+        switches.received("avtr_c").map(|r| (r.id, r.ok)),
+        Some(("avtr_b".to_string(), false))
+    );
+
+    switches.sent("avtr_d", at(2));
+    assert_eq!(
+        switches.sent("avtr_e", at(3)).map(|r| r.reason),
+        Some(Some("replaced by avtr_e".to_string()))
+    );
+    assert_eq!(switches.poll(timeout, at(32)), None);
+    assert_eq!(
+        switches.poll(timeout, at(33)).map(|r| (r.id, r.ok)),
+        Some(("avtr_e".to_string(), false))
+    );
+    assert_eq!(switches.poll(timeout, at(34)), None);
+}
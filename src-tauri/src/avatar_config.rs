@@ -0,0 +1,117 @@
+//! VRChat's per-avatar OSC config, `OSC/usr_*/Avatars/avtr_*.json` under its LocalLow folder.
+//! VRChat writes one when an avatar is loaded, so avatars worn before are known offline.
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AvatarConfig {
+    /// blueprint ID
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub parameters: Vec<AvatarParameter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AvatarParameter {
+    pub name: String,
+    /// where VRChat receives it, absent for read-only parameters
+    #[serde(default)]
+    pub input: Option<ParameterAddress>,
+    /// where VRChat sends it
+    #[serde(default)]
+    pub output: Option<ParameterAddress>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParameterAddress {
+    pub address: String,
+    /// `Int`, `Float` or `Bool`
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+/// `%USERPROFILE%\AppData\LocalLow\VRChat\VRChat\OSC` on Windows.
+pub fn default_dir() -> Option<PathBuf> {
+    if !cfg!(windows) {
+        return None;
+    }
+    let profile = std::env::var_os("USERPROFILE")?;
+    Some(
+        Path::new(&profile)
+            .join("AppData")
+            .join("LocalLow")
+            .join("VRChat")
+            .join("VRChat")
+            .join("OSC"),
+    )
+}
+
+/// Reads one config. VRChat writes them with a BOM.
+pub fn read_config(path: &Path) -> Result<AvatarConfig, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(text.trim_start_matches('\u{feff}'))?)
+}
+
+/// Config of one avatar, e.g. the one VRChat just switched to, without reading the others.
+/// The last user in name order wins, as in [`read_dir`].
+pub fn read_avatar(dir: &Path, id: &str) -> Option<AvatarConfig> {
+    let valid = id.starts_with("avtr_")
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        warn!("not a blueprint ID: {:?}", id);
+        return None;
+    }
+    let mut paths = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|user| user.file_name().to_string_lossy().starts_with("usr_"))
+        .map(|user| user.path().join("Avatars").join(format!("{}.json", id)))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+    let path = paths.pop()?;
+    match read_config(&path) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!("could not read avatar config {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Configs of every user in `dir` by blueprint ID. Broken files are logged and skipped.
+pub fn read_dir(dir: &Path) -> BTreeMap<String, AvatarConfig> {
+    let mut configs = BTreeMap::new();
+    let Ok(users) = std::fs::read_dir(dir) else {
+        debug!("no VRChat OSC config directory: {:?}", dir);
+        return configs;
+    };
+    let mut paths = users
+        .flatten()
+        .filter(|user| user.file_name().to_string_lossy().starts_with("usr_"))
+        .filter_map(|user| std::fs::read_dir(user.path().join("Avatars")).ok())
+        .flat_map(|avatars| avatars.flatten().map(|avatar| avatar.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "json")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("avtr_"))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        match read_config(&path) {
+            Ok(config) => {
+                configs.insert(config.id.clone(), config);
+            }
+            Err(e) => warn!("could not read avatar config {:?}: {}", path, e),
+        }
+    }
+    configs
+}
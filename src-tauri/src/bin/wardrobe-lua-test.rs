@@ -0,0 +1,9 @@
+//! Headless runner for wardrobe Lua scripts.
+//!
+//! ```shell
+//! cargo run --bin wardrobe-lua-test -- tests/lua/wardrobe.json
+//! ```
+
+fn main() -> std::process::ExitCode {
+    osc_wardrobe_lib::lua_test_main()
+}
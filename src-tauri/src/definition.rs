@@ -0,0 +1,87 @@
+use log::{trace, warn};
+use std::path::Path;
+
+/// Reads every `*.json` under `defs_dir` into one table keyed by its relative path.
+///
+/// `defs/aliases.json` becomes `definition.aliases`, `defs/avatars/avtr_xxx.json` becomes
+/// `definition.avatars.avtr_xxx`. Hidden files and directories are skipped.
+pub fn get_definition(defs_dir: &Path) -> serde_json::Value {
+    trace!("get definition: {:?}", defs_dir);
+    if !defs_dir.exists() {
+        return serde_json::Value::Null;
+    }
+    trace!("get definition _");
+    let mut table = serde_json::json!({});
+    for entry in walkdir::WalkDir::new(defs_dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+        .filter_map(|e| e.ok())
+    {
+        if !is_json(&entry) {
+            continue;
+        }
+        let path = entry.path();
+        let Some(keys) = get_keys(defs_dir, path) else {
+            continue;
+        };
+        // read json from file
+        let Ok(file) = std::fs::File::open(path) else {
+            warn!("could not open definition file: {:?}", path);
+            continue;
+        };
+        let Ok::<serde_json::value::Value, _>(json) = serde_json::from_reader(file) else {
+            warn!("could not parse definition file: {:?}", path);
+            continue;
+        };
+        set_value(&mut table, &keys, json);
+    }
+    table
+}
+fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| entry.depth() > 0 && s.starts_with("."))
+        .unwrap_or(false)
+}
+fn is_json(entry: &walkdir::DirEntry) -> bool {
+    if !entry.file_type().is_file() {
+        return false;
+    }
+    entry.path().extension() == Some("json".as_ref())
+}
+fn get_keys(root: &Path, path: &Path) -> Option<Vec<String>> {
+    let Some(stem) = path.file_stem() else {
+        return None;
+    };
+    let path = path.with_file_name(stem);
+    let Ok(sub) = path.strip_prefix(root) else {
+        return None;
+    };
+    Some(
+        sub.components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>(),
+    )
+}
+fn set_value(table: &mut serde_json::value::Value, keys: &[String], value: serde_json::Value) {
+    let Some((first_key, keys)) = keys.split_first() else {
+        return;
+    };
+    let Some(map) = table.as_object_mut() else {
+        return;
+    };
+    if keys.len() == 0 {
+        map.insert(first_key.into(), value);
+        return;
+    }
+    if let Some(maybe_table) = map.get_mut(first_key) {
+        if maybe_table.is_object() {
+            set_value(maybe_table, keys, value);
+            return;
+        }
+    }
+    map.insert(first_key.into(), serde_json::json!({}));
+    set_value(map.get_mut(first_key).unwrap(), keys, value);
+}
@@ -1,28 +1,125 @@
-mod application_event;
+//! OSCQuery app with Lua scripting for change avatar.
+//!
+//! The Tauri app is started by [`run`]. The wardrobe logic itself does not depend on Tauri and
+//! can be embedded in other tools and tests through [`lua::LuaEngine`]:
+//!
+//! ```no_run
+//! use osc_wardrobe_lib::application_event::ApplicationEvent;
+//! use osc_wardrobe_lib::definition::get_definition;
+//! use osc_wardrobe_lib::lua::{LuaEngine, LuaEngineEvent, LuaEngineOption};
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> mlua::Result<()> {
+//! let (event_sender, event_receiver) = tokio::sync::mpsc::unbounded_channel();
+//! let (osc_sender, mut osc_receiver) = tokio::sync::mpsc::unbounded_channel();
+//! let (print_sender, _print_receiver) = tokio::sync::mpsc::unbounded_channel();
+//! let mut engine = LuaEngine::new(LuaEngineOption {
+//!     base_dir: "lua".into(),
+//!     io_dir: "lua/io".into(),
+//!     lua_engine_event_receiver: event_receiver,
+//!     application_event_sender: Arc::new(osc_sender),
+//!     print_sender: Some(Arc::new(print_sender)),
+//!     settings: Default::default(),
+//!     osc_filter: Default::default(),
+//!     osc_cache: Default::default(),
+//! });
+//! engine.start().await?;
+//!
+//! let definition = get_definition(Path::new("defs"));
+//! event_sender.send(LuaEngineEvent::DefinitionUpdated(definition)).unwrap();
+//! event_sender
+//!     .send(LuaEngineEvent::OscReceived(
+//!         "/avatar/change".into(),
+//!         vec![rosc::OscType::String("avtr_xxx".into())],
+//!     ))
+//!     .unwrap();
+//! engine.process_event().await;
+//!
+//! while let Ok(event) = osc_receiver.try_recv() {
+//!     match event {
+//!         ApplicationEvent::SendOsc(addr, args) => println!("{} {:?}", addr, args),
+//!         ApplicationEvent::SendOscBundle(messages, _) => {
+//!             for (addr, args) in messages {
+//!                 println!("{} {:?}", addr, args);
+//!             }
+//!         }
+//!         other => println!("{:?}", other),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod application_event;
+pub(crate) mod avatar_catalog;
+pub(crate) mod avatar_change;
+pub mod avatar_config;
+pub mod definition;
 mod log_state;
-mod lua;
+pub mod lua;
+mod lua_test;
 mod osc;
-mod update;
+pub mod osc_cache;
+pub(crate) mod osc_lua;
+pub(crate) mod osc_pattern;
+pub(crate) mod osc_query;
+pub(crate) mod script_menu;
+pub(crate) mod settings;
+mod shutdown;
+pub(crate) mod update;
+#[cfg(test)]
+mod update_check_test;
 
 use crate::application_event::ApplicationEvent;
+use crate::definition::get_definition;
 use crate::lua::LuaEngineEvent;
 use log::*;
 use log_state::{get_logs, LogState};
 use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
-use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use settings::{get_settings, set_settings, Settings};
+use shutdown::Shutdown;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tauri::menu::{Menu, MenuItem, PredefinedMenuItem, SubmenuBuilder};
+use tauri::menu::{
+    CheckMenuItem, IsMenuItem, Menu, MenuItem, MenuItemKind, PredefinedMenuItem, Submenu,
+    SubmenuBuilder,
+};
 use tauri::path::BaseDirectory;
 use tauri::tray::{TrayIconBuilder, TrayIconEvent};
-use tauri::{App, AppHandle, Manager};
+use tauri::{App, AppHandle, Emitter, Manager};
 use tauri_plugin_cli::CliExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 #[derive(Clone)]
 struct AppState {
     log_state: Arc<Mutex<log_state::LogState>>,
-    application_event_sender: Sender<ApplicationEvent>,
+    application_event_sender: UnboundedSender<ApplicationEvent>,
     tray_icon: Arc<Mutex<Option<tauri::tray::TrayIcon>>>,
+    settings: Arc<tokio::sync::watch::Sender<Settings>>,
+    /// newer release the user has not dismissed yet
+    update: Arc<Mutex<Option<update::UpdateInfo>>>,
+    /// shown in the tray menu
+    osc_health: Arc<Mutex<osc::OscHealth>>,
+    /// last avatar switch VRChat did not confirm, shown in the tray menu until one succeeds
+    avatar_switch_failure: Arc<Mutex<Option<String>>>,
+    /// VRChat's OSC configs by blueprint ID
+    avatar_configs: Arc<Mutex<BTreeMap<String, avatar_config::AvatarConfig>>>,
+    avatar_catalog: Arc<Mutex<avatar_catalog::AvatarCatalog>>,
+    /// `definition.aliases`, blueprint IDs by alias for "Switch to" in the tray menu
+    aliases: Arc<Mutex<BTreeMap<String, String>>>,
+    /// `wardrobe.menu` items of scripts
+    script_menu: Arc<Mutex<Vec<script_menu::ScriptMenuItem>>>,
+    /// `false` while the event processor would run [`Shutdown`] first
+    exit_ready: Arc<AtomicBool>,
+}
+
+/// Entry point of the `wardrobe-lua-test` binary, see [`lua::LuaEngine`] for embedding instead.
+#[doc(hidden)]
+pub fn lua_test_main() -> std::process::ExitCode {
+    lua_test::main()
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -44,7 +141,7 @@ fn reload_lua(state: tauri::State<Mutex<AppState>>) {
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let (log_channel, log_receiver) = LogState::create();
-    let (lua_log_sender, lua_log_receiver) = channel::<String>();
+    let (lua_log_sender, lua_log_receiver) = unbounded_channel::<String>();
     nyquest_preset::register();
     tauri::Builder::default()
         .plugin(tauri_plugin_cli::init())
@@ -52,7 +149,7 @@ pub fn run() {
             tauri_plugin_log::Builder::new()
                 .target(tauri_plugin_log::Target::new(
                     tauri_plugin_log::TargetKind::Dispatch(
-                        fern::Dispatch::new().chain(log_channel.sender.clone()),
+                        fern::Dispatch::new().chain(log_channel.output()),
                     ),
                 ))
                 .level(LevelFilter::Trace)
@@ -63,25 +160,91 @@ pub fn run() {
                 .build(),
         )
         .setup(|app| {
-            let (tx, rx) = channel();
+            let (tx, rx) = unbounded_channel();
             let log_state = Arc::new(Mutex::new(log_channel));
             let lua_log_sender = Some(lua_log_sender);
             let lua_log_receiver = Some(lua_log_receiver);
+            let settings = settings::load(&settings_path(app.app_handle()));
+            log::set_max_level(settings.level_filter());
+            let settings = Arc::new(tokio::sync::watch::Sender::new(settings));
             app.manage(Mutex::new(AppState {
                 log_state: log_state.clone(),
                 application_event_sender: tx.clone(),
                 tray_icon: Arc::new(Mutex::new(None)),
+                settings: settings.clone(),
+                update: Arc::new(Mutex::new(None)),
+                osc_health: Arc::new(Mutex::new(osc::OscHealth::Waiting)),
+                avatar_switch_failure: Arc::new(Mutex::new(None)),
+                avatar_configs: Arc::new(Mutex::new(BTreeMap::new())),
+                avatar_catalog: Arc::new(Mutex::new(avatar_catalog::AvatarCatalog::load(
+                    &avatar_catalog_path(app.app_handle()),
+                ))),
+                aliases: Arc::new(Mutex::new(BTreeMap::new())),
+                script_menu: Arc::new(Mutex::new(vec![])),
+                exit_ready: Arc::new(AtomicBool::new(true)),
             }));
-            tauri::async_runtime::spawn(async move {
-                LogState::process(log_state, log_receiver, lua_log_receiver).await;
-            });
-            auto_update(app.app_handle());
+            let mut shutdown = Shutdown::default();
+            let log_signal = shutdown.log_signal();
+            shutdown.set_log_task(tauri::async_runtime::spawn(async move {
+                LogState::process(log_state, log_receiver, lua_log_receiver, log_signal).await;
+            }));
+            if apply_staged_update(app.app_handle()) {
+                // nothing else is started, so this only flushes the log
+                let app = app.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    shutdown.run().await;
+                    app.exit(0);
+                });
+                return Ok(());
+            }
+            auto_update(app.app_handle(), &settings.borrow());
             let (tx2, rx2) = tokio::sync::mpsc::channel(1000);
-            let lua_engine_event_sender = setup_lua(app, tx.clone(), lua_log_sender)?;
-            setup_definitions(app, lua_engine_event_sender.clone())?;
-            setup_tray_menu(app, tx.clone())?;
-            let osc_receiver = setup_osc_server(app, rx2);
-            setup_event_processor(app, rx, osc_receiver, lua_engine_event_sender, tx2);
+            let osc_cache = osc_cache::OscCache::default();
+            let (lua_engine_event_sender, lua_stopped, osc_filter) = setup_lua(
+                app,
+                tx.clone(),
+                lua_log_sender,
+                osc_cache.clone(),
+                &settings.borrow(),
+            )?;
+            shutdown.set_lua(lua_engine_event_sender.clone(), lua_stopped);
+            send_catalog(app.app_handle(), &lua_engine_event_sender);
+            setup_settings_listener(
+                app.app_handle().clone(),
+                settings.subscribe(),
+                lua_engine_event_sender.clone(),
+                &mut shutdown,
+            );
+            setup_definitions(
+                app,
+                lua_engine_event_sender.clone(),
+                settings.subscribe(),
+                &mut shutdown,
+            )?;
+            {
+                let (app, lua_sender) = (app.app_handle().clone(), lua_engine_event_sender.clone());
+                tauri::async_runtime::spawn_blocking(move || {
+                    load_avatar_configs(&app, &lua_sender)
+                });
+            }
+            setup_tray_menu(app, tx.clone(), lua_engine_event_sender.clone())?;
+            let (endpoints_sender, endpoints) = tokio::sync::watch::channel(vec![]);
+            let osc_receiver =
+                setup_osc_server(app, rx2, settings.subscribe(), endpoints, &mut shutdown);
+            setup_event_processor(
+                app,
+                rx,
+                osc_receiver,
+                lua_engine_event_sender,
+                OscLinks {
+                    filter: osc_filter,
+                    cache: osc_cache,
+                    requests: tx2,
+                    endpoints: endpoints_sender,
+                },
+                settings.subscribe(),
+                shutdown,
+            );
             info!("setup done.");
             Ok(())
         })
@@ -92,16 +255,48 @@ pub fn run() {
             }
         })
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet, reload_lua, get_logs])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            reload_lua,
+            get_logs,
+            get_settings,
+            set_settings,
+            get_update,
+            update_action,
+            get_avatar_configs,
+            get_avatar_catalog
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // an exit of the OS or the last window waits for the shutdown of the event processor
+            if let tauri::RunEvent::ExitRequested { api, .. } = event {
+                let Some(state) = app.try_state::<Mutex<AppState>>() else {
+                    return;
+                };
+                let state = state.lock().expect("get AppState");
+                if !state.exit_ready.load(Ordering::SeqCst) {
+                    api.prevent_exit();
+                    let _ = state.application_event_sender.send(ApplicationEvent::Exit);
+                }
+            }
+        });
 }
 
 fn setup_lua(
     app: &App,
-    tx: Sender<ApplicationEvent>,
-    log_sender: Option<Sender<String>>,
-) -> Result<Sender<LuaEngineEvent>, Box<dyn std::error::Error>> {
+    tx: UnboundedSender<ApplicationEvent>,
+    log_sender: Option<UnboundedSender<String>>,
+    osc_cache: osc_cache::OscCache,
+    settings: &Settings,
+) -> Result<
+    (
+        UnboundedSender<LuaEngineEvent>,
+        tokio::sync::oneshot::Receiver<()>,
+        lua::OscFilter,
+    ),
+    Box<dyn std::error::Error>,
+> {
     debug!("extract lua directory");
     let lua_dir_src = app
         .path()
@@ -128,15 +323,25 @@ fn setup_lua(
         Err(_) => {}
     }
 
-    if lua::extract_lua_dir_if_needed(lua_dir_src, &lua_dir)? {
+    if lua::extract_lua_dir_if_needed(&lua_dir_src, &lua_dir)? {
         debug!("extracted: {:?}", lua_dir);
     } else {
         debug!("already exists");
     }
+    for conflict in lua::upgrade_lua_dir(&lua_dir_src, &lua_dir)? {
+        warn!(
+            "bundled lua is updated but yours is edited, see {:?}",
+            conflict
+        );
+    }
     std::env::set_current_dir(&lua_dir)?;
 
     debug!("spawn lua_thread");
-    let (tx2, rx2) = channel();
+    let (tx2, rx2) = unbounded_channel();
+    let (stopped_sender, stopped_receiver) = tokio::sync::oneshot::channel();
+    let lua_settings = settings.lua.clone();
+    let osc_filter = lua::OscFilter::default();
+    let osc_filter_ = osc_filter.clone();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -147,106 +352,116 @@ fn setup_lua(
                 base_dir: lua_dir,
                 io_dir: lua_io_dir,
                 lua_engine_event_receiver: rx2,
-                application_event_sender: tx,
-                print_sender: log_sender,
+                application_event_sender: Arc::new(tx),
+                print_sender: log_sender.map(|s| Arc::new(s) as Arc<dyn lua::Sink<String>>),
+                settings: lua_settings,
+                osc_filter: osc_filter_,
+                osc_cache,
             });
-            let _ = engine.start().await;
-
-            loop {
-                let process_event_count = engine.process_event().await;
-                if process_event_count == 0 {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
-                }
+            if let Err(e) = engine.start().await {
+                warn!("error on start: {:?}", e);
             }
+            engine.run().await;
         });
+        let _ = stopped_sender.send(());
     });
-    Ok(tx2)
+    Ok((tx2, stopped_receiver, osc_filter))
 }
 
-fn get_definition(defs_dir: &PathBuf) -> serde_json::Value {
-    trace!("get definition: {:?}", defs_dir);
-    if !defs_dir.exists() {
-        return serde_json::Value::Null;
-    }
-    trace!("get definition _");
-    let mut table = serde_json::json!({});
-    for entry in walkdir::WalkDir::new(defs_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_entry(|e| !is_hidden(e))
-        .filter_map(|e| e.ok())
-    {
-        if !is_json(&entry) {
-            continue;
+/// Applies changed settings which are not watched by each subsystem.
+fn setup_settings_listener(
+    app: AppHandle,
+    mut settings: tokio::sync::watch::Receiver<Settings>,
+    lua_event_sender: UnboundedSender<LuaEngineEvent>,
+    shutdown: &mut Shutdown,
+) {
+    let mut signal = shutdown.signal();
+    let mut vrchat_osc_dir = settings.borrow().osc.vrchat_osc_dir.clone();
+    let handle = tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::select! {
+                Ok(()) = settings.changed() => {
+                    let settings = settings.borrow_and_update().clone();
+                    debug!("settings changed: {:?}", settings);
+                    log::set_max_level(settings.level_filter());
+                    if settings.osc.vrchat_osc_dir != vrchat_osc_dir {
+                        vrchat_osc_dir = settings.osc.vrchat_osc_dir.clone();
+                        let (app, lua_sender) = (app.clone(), lua_event_sender.clone());
+                        tauri::async_runtime::spawn_blocking(move || {
+                            load_avatar_configs(&app, &lua_sender)
+                        });
+                    }
+                    send_lua(&lua_event_sender, LuaEngineEvent::SettingsUpdated(settings.lua));
+                },
+                _ = signal.wait_for(|stop| *stop) => break,
+            }
         }
-        let path = entry.path();
-        let Some(keys) = get_keys(defs_dir, path) else {
-            continue;
-        };
-        // read json from file
-        let Ok(file) = std::fs::File::open(path) else {
-            warn!("could not open definition file: {:?}", path);
-            continue;
-        };
-        let Ok::<serde_json::value::Value, _>(json) = serde_json::from_reader(file) else {
-            warn!("could not parse definition file: {:?}", path);
-            continue;
-        };
-        set_value(&mut table, &keys, json);
-    }
-    table
-}
-fn is_hidden(entry: &walkdir::DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| entry.depth() > 0 && s.starts_with("."))
-        .unwrap_or(false)
+    });
+    shutdown.add_task("settings listener", handle);
 }
-fn is_json(entry: &walkdir::DirEntry) -> bool {
-    if !entry.file_type().is_file() {
-        return false;
-    }
-    entry.path().extension() == Some("json".as_ref())
+
+fn watch_definitions(
+    defs_dir: &std::path::Path,
+    debounce_ms: u64,
+    tx: UnboundedSender<notify_debouncer_mini::DebounceEventResult>,
+) -> Result<
+    notify_debouncer_mini::Debouncer<notify_debouncer_mini::notify::RecommendedWatcher>,
+    notify_debouncer_mini::notify::Error,
+> {
+    let mut debouncer = new_debouncer(
+        std::time::Duration::from_millis(debounce_ms),
+        move |event: notify_debouncer_mini::DebounceEventResult| {
+            let _ = tx.send(event);
+        },
+    )?;
+    debouncer
+        .watcher()
+        .watch(defs_dir, RecursiveMode::Recursive)?;
+    Ok(debouncer)
 }
-fn get_keys(root: &PathBuf, path: &Path) -> Option<Vec<String>> {
-    let Some(stem) = path.file_stem() else {
-        return None;
-    };
-    let path = path.with_file_name(stem);
-    let Ok(sub) = path.strip_prefix(root) else {
-        return None;
-    };
-    Some(
-        sub.components()
-            .map(|c| c.as_os_str().to_string_lossy().into_owned())
-            .collect::<Vec<_>>(),
-    )
-}
-fn set_value(table: &mut serde_json::value::Value, keys: &[String], value: serde_json::Value) {
-    let Some((first_key, keys)) = keys.split_first() else {
-        return;
-    };
-    let Some(map) = table.as_object_mut() else {
-        return;
+
+/// Reads definitions for Lua, and their aliases for the catalog and the tray menu.
+fn send_definition(
+    app: &AppHandle,
+    lua_event_sender: &UnboundedSender<LuaEngineEvent>,
+    defs_dir: &std::path::Path,
+) {
+    let definition = get_definition(defs_dir);
+    update_catalog(app, lua_event_sender, |catalog| {
+        catalog.merge_aliases(&definition)
+    });
+    let aliases = definition
+        .get("aliases")
+        .and_then(|a| a.as_object())
+        .map(|aliases| {
+            aliases
+                .iter()
+                .filter_map(|(alias, id)| Some((alias.clone(), id.as_str()?.to_string())))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .unwrap_or_default();
+    let changed = {
+        let state = app.state::<Mutex<AppState>>();
+        let state = state.lock().expect("state.");
+        let mut current = state.aliases.lock().expect("state.aliases");
+        let changed = *current != aliases;
+        *current = aliases;
+        changed
     };
-    if keys.len() == 0 {
-        map.insert(first_key.into(), value);
-        return;
+    if changed {
+        reload_menu(app);
     }
-    if let Some(maybe_table) = map.get_mut(first_key) {
-        if maybe_table.is_object() {
-            set_value(maybe_table, keys, value);
-            return;
-        }
-    }
-    map.insert(first_key.into(), serde_json::json!({}));
-    set_value(map.get_mut(first_key).unwrap(), keys, value);
+    send_lua(
+        lua_event_sender,
+        LuaEngineEvent::DefinitionUpdated(definition),
+    );
 }
 
 fn setup_definitions(
     app: &App,
-    lua_event_sender: Sender<LuaEngineEvent>,
+    lua_event_sender: UnboundedSender<LuaEngineEvent>,
+    mut settings: tokio::sync::watch::Receiver<Settings>,
+    shutdown: &mut Shutdown,
 ) -> Result<(), Box<dyn std::error::Error>> {
     trace!("setup definitions");
     let app_handle = app.app_handle().clone();
@@ -254,73 +469,70 @@ fn setup_definitions(
     if !defs_dir.exists() {
         std::fs::create_dir_all(&defs_dir)?;
     }
-    lua_event_sender
-        .send(LuaEngineEvent::DefinitionUpdated(get_definition(&defs_dir)))
-        .unwrap();
+    send_definition(&app_handle, &lua_event_sender, &defs_dir);
 
-    let (tx, rx) = channel();
-    let mut debouncer = new_debouncer(std::time::Duration::from_secs(2), tx)?;
-    let _ = tauri::async_runtime::spawn(async move {
-        let watcher = debouncer.watcher();
-        watcher
-            .watch(&defs_dir, RecursiveMode::Recursive)
-            .expect("watcher start");
+    let (tx, mut rx) = unbounded_channel();
+    let mut debounce_ms = settings.borrow_and_update().definitions_debounce_ms;
+    let mut debouncer = watch_definitions(&defs_dir, debounce_ms, tx.clone())?;
+    let mut signal = shutdown.signal();
+    let handle = tauri::async_runtime::spawn(async move {
         loop {
-            tokio::task::yield_now().await;
-            match rx.try_recv() { Ok(event) => {
-                match event {
+            tokio::select! {
+                Some(event) = rx.recv() => { match event {
                     Ok(event) => {
                         debug!("event: {:?}", event);
-                        lua_event_sender
-                            .send(LuaEngineEvent::DefinitionUpdated(get_definition(&defs_dir)))
-                            .unwrap();
+                        send_definition(&app_handle, &lua_event_sender, &defs_dir);
                     }
                     Err(e) => {
                         warn!("notify error: {:?}", e);
                     }
-                }
-            } _ => {
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            }}
+                } },
+                Ok(()) = settings.changed() => {
+                    let new_debounce_ms = settings.borrow_and_update().definitions_debounce_ms;
+                    if new_debounce_ms != debounce_ms {
+                        match watch_definitions(&defs_dir, new_debounce_ms, tx.clone()) {
+                            Ok(new_debouncer) => {
+                                debug!("definitions debounce: {} ms", new_debounce_ms);
+                                debouncer = new_debouncer;
+                                debounce_ms = new_debounce_ms;
+                            }
+                            Err(e) => warn!("could not restart watcher: {:?}", e),
+                        }
+                    }
+                },
+                _ = signal.wait_for(|stop| *stop) => break,
+            }
         }
+        drop(debouncer);
+        debug!("definitions watcher stopped");
     });
+    shutdown.add_task("definitions watcher", handle);
     Ok(())
 }
 
 fn setup_osc_server(
     app: &mut App,
-    receiver: tokio::sync::mpsc::Receiver<osc::OscEvent>,
-) -> tokio::sync::mpsc::UnboundedReceiver<osc::OscEvent> {
+    receiver: tokio::sync::mpsc::Receiver<osc::OscRequest>,
+    settings: tokio::sync::watch::Receiver<Settings>,
+    endpoints: tokio::sync::watch::Receiver<Vec<osc_query::OscEndpoint>>,
+    shutdown: &mut Shutdown,
+) -> UnboundedReceiver<osc::OscEvent> {
     let app_handle = app.app_handle();
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let (tx, rx) = unbounded_channel();
     debug!("setup_osc_server: start");
-    let _osc_handle = tauri::async_runtime::spawn(async move {
-        osc::OscService::process_osc(tx, receiver).await.unwrap();
+    let signal = shutdown.signal();
+    let osc_handle = tauri::async_runtime::spawn(async move {
+        if let Err(e) =
+            osc::OscService::process_osc(tx, receiver, settings, endpoints, signal).await
+        {
+            error!("OSC service stopped: {}", e);
+        }
     });
+    shutdown.add_task("osc", osc_handle);
     rx
 }
-fn osc_to_json(v: &rosc::OscType) -> serde_json::Value {
-    use rosc::OscType::*;
-    use serde_json::json;
-    match v {
-        Int(i) => json!(i),
-        Float(f) => json!(f),
-        String(s) => json!(s),
-        Long(l) => json!(l.to_string()),
-        Double(d) => json!(d),
-        Char(c) => json!(c),
-        Color(color) => json!((color.red, color.green, color.blue, color.alpha)),
-        Bool(b) => json!(b),
-        Array(arr) => arr.content.iter().map(osc_to_json).collect(),
-        Nil => json!(null),
-        Inf => json!(f32::INFINITY),
-        _ => {
-            debug!("not implemented for {:?}", v);
-            json!(null)
-        }
-    }
-}
-fn json_to_osc(v: &serde_json::Value) -> rosc::OscType {
+/// Converts a JSON value of settings or test files to an OSC argument. Numbers become float.
+pub fn json_to_osc(v: &serde_json::Value) -> rosc::OscType {
     use serde_json::Value::*;
     match v {
         Bool(b) => rosc::OscType::Bool(*b),
@@ -336,73 +548,496 @@ fn json_to_osc(v: &serde_json::Value) -> rosc::OscType {
         }
     }
 }
+const AVATAR_CHANGE: &str = "/avatar/change";
+
+/// `id` of `/avatar/change id` to VRChat, which goes through [`avatar_change::AvatarChangeGuard`]
+fn avatar_change_target<'a>(
+    target: &str,
+    addr: &str,
+    args: &'a [rosc::OscType],
+) -> Option<&'a str> {
+    match args {
+        [rosc::OscType::String(id)] if target == osc::VRCHAT_TARGET && addr == AVATAR_CHANGE => {
+            Some(id)
+        }
+        _ => None,
+    }
+}
+
+/// Logs what the guard does with the change to `id`, and returns `true` to send it now.
+fn guard_avatar_change(
+    guard: &mut avatar_change::AvatarChangeGuard,
+    id: &str,
+    current: Option<&str>,
+    cooldown: std::time::Duration,
+) -> bool {
+    match guard.request(id, current, cooldown, std::time::Instant::now()) {
+        avatar_change::Decision::Send => true,
+        avatar_change::Decision::Defer { replaced, .. } => {
+            if let Some(replaced) = replaced {
+                info!(
+                    "avatar change to {} suppressed: replaced by {}",
+                    replaced, id
+                );
+            }
+            debug!("avatar change to {} waits for cooldown", id);
+            false
+        }
+        avatar_change::Decision::Suppress { reason, cancelled } => {
+            if let Some(cancelled) = cancelled {
+                info!(
+                    "avatar change to {} suppressed: replaced by {}",
+                    cancelled, id
+                );
+            }
+            info!("avatar change to {} suppressed: {}", id, reason);
+            false
+        }
+    }
+}
+
+/// Runs an outgoing change to `id` through the guard, and returns `true` to send it now.
+fn allow_avatar_change(
+    app: &AppHandle,
+    lua_sender: &UnboundedSender<LuaEngineEvent>,
+    guard: &mut avatar_change::AvatarChangeGuard,
+    switches: &mut avatar_change::SwitchConfirmation,
+    id: &str,
+    current: Option<&str>,
+    cooldown: std::time::Duration,
+) -> bool {
+    if !guard_avatar_change(guard, id, current, cooldown) {
+        return false;
+    }
+    let previous = switches.sent(id, std::time::Instant::now());
+    report_switch_result(app, lua_sender, previous);
+    true
+}
+
+/// `id` of incoming `/avatar/change id`, VRChat switched to it
+fn reported_avatar(message: &rosc::OscMessage) -> Option<&str> {
+    match message.args.first() {
+        Some(rosc::OscType::String(id)) if message.addr == AVATAR_CHANGE => Some(id),
+        _ => None,
+    }
+}
+
+/// VRChat switched to `id`.
+fn avatar_reported(
+    app: &AppHandle,
+    lua_sender: &UnboundedSender<LuaEngineEvent>,
+    switches: &mut avatar_change::SwitchConfirmation,
+    id: &str,
+) {
+    report_switch_result(app, lua_sender, switches.received(id));
+    update_catalog(app, lua_sender, |catalog| {
+        catalog.seen(id, chrono::Utc::now())
+    });
+    let (app, lua_sender, id) = (app.clone(), lua_sender.clone(), id.to_string());
+    tauri::async_runtime::spawn_blocking(move || load_avatar_config(&app, &lua_sender, &id));
+}
+
+/// Passes the catalog as loaded to Lua, since [`update_catalog`] does only on changes.
+fn send_catalog(app: &AppHandle, lua_sender: &UnboundedSender<LuaEngineEvent>) {
+    let avatars = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .avatar_catalog
+        .lock()
+        .expect("state.avatar_catalog")
+        .avatars
+        .clone();
+    send_lua(
+        lua_sender,
+        LuaEngineEvent::CatalogUpdated(
+            serde_json::to_value(&avatars).expect("avatar catalog to json"),
+        ),
+    );
+}
+
+/// Applies `f` to the catalog, then saves and passes it on if `f` returns `true`.
+fn update_catalog(
+    app: &AppHandle,
+    lua_sender: &UnboundedSender<LuaEngineEvent>,
+    f: impl FnOnce(&mut avatar_catalog::AvatarCatalog) -> bool,
+) {
+    let catalog = {
+        let state = app.state::<Mutex<AppState>>();
+        let state = state.lock().expect("state.");
+        let mut catalog = state.avatar_catalog.lock().expect("state.avatar_catalog");
+        if !f(&mut catalog) {
+            return;
+        }
+        catalog.clone()
+    };
+    {
+        let app = app.clone();
+        tauri::async_runtime::spawn_blocking(move || save_catalog(&app));
+    }
+    send_lua(
+        lua_sender,
+        LuaEngineEvent::CatalogUpdated(
+            serde_json::to_value(&catalog.avatars).expect("avatar catalog to json"),
+        ),
+    );
+    if let Err(e) = app.emit("avatar-catalog-updated", &catalog.avatars) {
+        warn!("emit avatar-catalog-updated: {:?}", e);
+    }
+}
+
+/// Writes the catalog as it is now, off the event loop. Saves one at a time, so the last one
+/// written is never older than the last change.
+fn save_catalog(app: &AppHandle) {
+    static SAVING: Mutex<()> = Mutex::new(());
+    let _saving = SAVING.lock().expect("catalog save");
+    let catalog = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .avatar_catalog
+        .lock()
+        .expect("state.avatar_catalog")
+        .clone();
+    if let Err(e) = catalog.save(&avatar_catalog_path(app)) {
+        warn!("could not save avatar catalog: {}", e);
+    }
+}
+
+/// Logs the result, shows a failure in the tray and passes it to Lua.
+fn report_switch_result(
+    app: &AppHandle,
+    lua_sender: &UnboundedSender<LuaEngineEvent>,
+    result: Option<avatar_change::SwitchResult>,
+) {
+    let Some(result) = result else {
+        return;
+    };
+    let failure = if result.ok {
+        info!("avatar switched to {}", result.id);
+        None
+    } else {
+        let reason = result.reason.as_deref().unwrap_or_default();
+        warn!("avatar switch to {} failed: {}", result.id, reason);
+        Some(format!("{} ({})", result.id, reason))
+    };
+    let changed = {
+        let state = app.state::<Mutex<AppState>>();
+        let state = state.lock().expect("state.");
+        let mut current = state
+            .avatar_switch_failure
+            .lock()
+            .expect("state.avatar_switch_failure");
+        let changed = *current != failure;
+        *current = failure;
+        changed
+    };
+    if changed {
+        reload_menu(app);
+    }
+    send_lua(lua_sender, LuaEngineEvent::AvatarSwitchResult(result));
+}
+
+/// Passes `event` to Lua, which is gone once it has stopped on shutdown.
+fn send_lua(lua_sender: &UnboundedSender<LuaEngineEvent>, event: LuaEngineEvent) {
+    if let Err(e) = lua_sender.send(event) {
+        debug!("lua is stopped, drop {:?}", e.0);
+    }
+}
+
+/// What the event processor shares with the OSC service and the Lua engine.
+struct OscLinks {
+    filter: lua::OscFilter,
+    cache: osc_cache::OscCache,
+    requests: tokio::sync::mpsc::Sender<osc::OscRequest>,
+    endpoints: tokio::sync::watch::Sender<Vec<osc_query::OscEndpoint>>,
+}
+
+impl OscLinks {
+    async fn send_message(&self, target: String, message: rosc::OscMessage) {
+        self.requests
+            .send(osc::OscRequest::Send(osc::Outgoing {
+                target,
+                packet: rosc::OscPacket::Message(message),
+            }))
+            .await
+            .unwrap_or_else(|err| {
+                warn!("failed to send OSC message (mpsc event queue): {:?}", err);
+            });
+    }
+
+    /// blueprint ID VRChat reported last
+    fn current_avatar(&self) -> Option<String> {
+        match self.cache.get(AVATAR_CHANGE)?.args.first()? {
+            rosc::OscType::String(id) => Some(id.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records `message` in the cache and passes it to Lua if scripts want it.
+    fn receive(
+        &self,
+        message: rosc::OscMessage,
+        changed_only: bool,
+        lua_sender: &UnboundedSender<LuaEngineEvent>,
+    ) {
+        let changed = self
+            .cache
+            .update(&message.addr, &message.args, std::time::SystemTime::now());
+        if (changed || !changed_only) && self.filter.matches(&message.addr) {
+            send_lua(
+                lua_sender,
+                LuaEngineEvent::OscReceived(message.addr, message.args),
+            );
+        }
+    }
+}
+
 fn setup_event_processor(
     app: &mut App,
-    application_event_receiver: Receiver<ApplicationEvent>,
-    mut osc_receiver: tokio::sync::mpsc::UnboundedReceiver<osc::OscEvent>,
-    lua_sender: Sender<LuaEngineEvent>,
-    osc_sender: tokio::sync::mpsc::Sender<osc::OscEvent>,
+    mut application_event_receiver: UnboundedReceiver<ApplicationEvent>,
+    mut osc_receiver: UnboundedReceiver<osc::OscEvent>,
+    lua_sender: UnboundedSender<LuaEngineEvent>,
+    links: OscLinks,
+    settings: tokio::sync::watch::Receiver<Settings>,
+    mut shutdown: Shutdown,
 ) {
     let app_handle = app.app_handle().clone();
+    let osc_sender = links.requests.clone();
+    let (application_event_sender, exit_ready) = {
+        let state = app.state::<Mutex<AppState>>();
+        let state = state.lock().expect("get AppState");
+        (
+            state.application_event_sender.clone(),
+            state.exit_ready.clone(),
+        )
+    };
+    exit_ready.store(false, Ordering::SeqCst);
     let _ = tauri::async_runtime::spawn(async move {
+        let mut stopping = false;
+        let mut avatar_guard = avatar_change::AvatarChangeGuard::default();
+        let mut switches = avatar_change::SwitchConfirmation::default();
         loop {
-            tokio::task::yield_now().await;
-            if let Ok(app_event) = application_event_receiver.try_recv() {
-                match app_event {
+            let cooldown =
+                std::time::Duration::from_millis(settings.borrow().osc.avatar_change_cooldown_ms);
+            let deadline = avatar_guard.deadline(cooldown);
+            let timeout =
+                std::time::Duration::from_millis(settings.borrow().osc.avatar_change_timeout_ms);
+            let switch_deadline = switches.deadline(timeout);
+            tokio::select! {
+                Some(app_event) = application_event_receiver.recv() => { match app_event {
                     ApplicationEvent::Exit => {
+                        if stopping {
+                            continue;
+                        }
+                        stopping = true;
+                        // scripts may still send in `stop()`, which this loop keeps forwarding
+                        let stop_lua = shutdown.stop_lua();
+                        let sender = application_event_sender.clone();
+                        tauri::async_runtime::spawn(async move {
+                            stop_lua.await;
+                            let _ = sender.send(ApplicationEvent::LuaStopped);
+                        });
+                    }
+                    ApplicationEvent::LuaStopped => {
+                        shutdown.run().await;
+                        exit_ready.store(true, Ordering::SeqCst);
                         app_handle.exit(0);
+                        break;
                     }
                     ApplicationEvent::SendOsc(addr, args) => {
+                        let target = osc::VRCHAT_TARGET.to_string();
+                        if let Some(id) = avatar_change_target(&target, &addr, &args) {
+                            let current = links.current_avatar();
+                            if !allow_avatar_change(&app_handle, &lua_sender, &mut avatar_guard, &mut switches, id, current.as_deref(), cooldown) {
+                                continue;
+                            }
+                        }
+                        links.send_message(target, rosc::OscMessage { addr, args }).await;
+                    }
+                    ApplicationEvent::SendOscToClient(name, addr, args) => {
+                        // a handshake goes to a VRChat client too
+                        if let Some(id) = avatar_change_target(osc::VRCHAT_TARGET, &addr, &args) {
+                            let current = links.current_avatar();
+                            if !allow_avatar_change(&app_handle, &lua_sender, &mut avatar_guard, &mut switches, id, current.as_deref(), cooldown) {
+                                continue;
+                            }
+                        }
                         osc_sender
-                            .send(osc::OscEvent::Message(rosc::OscMessage {
-                                addr,
-                                args: args
-                                    .as_array()
-                                    .expect("args is array")
-                                    .iter()
-                                    .map(json_to_osc)
-                                    .collect(),
-                            }))
+                            .send(osc::OscRequest::SendToClient(
+                                name,
+                                rosc::OscPacket::Message(rosc::OscMessage { addr, args }),
+                            ))
                             .await
                             .unwrap_or_else(|err| {
                                 warn!("failed to send OSC message (mpsc event queue): {:?}", err);
                             });
                     }
-                    ApplicationEvent::ReloadLua => lua_sender
-                        .send(LuaEngineEvent::Reload)
-                        .expect("failed to send LuaEngineEvent::Reload"),
-                }
-            }
-            tokio::select! {
+                    ApplicationEvent::SendOscTo(target, addr, args) => {
+                        if let Some(id) = avatar_change_target(&target, &addr, &args) {
+                            let current = links.current_avatar();
+                            if !allow_avatar_change(&app_handle, &lua_sender, &mut avatar_guard, &mut switches, id, current.as_deref(), cooldown) {
+                                continue;
+                            }
+                        }
+                        links.send_message(target, rosc::OscMessage { addr, args }).await;
+                    }
+                    ApplicationEvent::SendOscBundle(messages, time) => {
+                        let timetag = time.unwrap_or(osc::IMMEDIATELY);
+                        // a change the guard holds back is left out, and sent on its own later
+                        let current = links.current_avatar();
+                        let content = messages
+                            .into_iter()
+                            .filter(|(addr, args)| {
+                                match avatar_change_target(osc::VRCHAT_TARGET, addr, args) {
+                                    Some(id) => allow_avatar_change(&app_handle, &lua_sender, &mut avatar_guard, &mut switches, id, current.as_deref(), cooldown),
+                                    None => true,
+                                }
+                            })
+                            .map(|(addr, args)| {
+                                rosc::OscPacket::Message(rosc::OscMessage { addr, args })
+                            })
+                            .collect::<Vec<_>>();
+                        if content.is_empty() {
+                            continue;
+                        }
+                        osc_sender
+                            .send(osc::OscRequest::Send(osc::Outgoing {
+                                target: osc::VRCHAT_TARGET.to_string(),
+                                packet: rosc::OscPacket::Bundle(rosc::OscBundle { timetag, content }),
+                            }))
+                            .await
+                            .unwrap_or_else(|err| {
+                                warn!("failed to send OSC bundle (mpsc event queue): {:?}", err);
+                            });
+                    }
+                    ApplicationEvent::SelectOscClient(name) => {
+                        osc_sender
+                            .send(osc::OscRequest::SelectClient(name))
+                            .await
+                            .unwrap_or_else(|err| {
+                                warn!("failed to select OSC client (mpsc event queue): {:?}", err);
+                            });
+                    }
+                    ApplicationEvent::SetOscEndpoints(endpoints) => {
+                        links.endpoints.send_replace(endpoints);
+                    }
+                    ApplicationEvent::SetScriptMenu(items) => {
+                        *app_handle
+                            .state::<Mutex<AppState>>()
+                            .lock()
+                            .expect("state.")
+                            .script_menu
+                            .lock()
+                            .expect("state.script_menu") = items;
+                        reload_menu(&app_handle);
+                    }
+                    ApplicationEvent::ReloadLua => send_lua(&lua_sender, LuaEngineEvent::Reload),
+                } },
                 Some(osc_msg) = osc_receiver.recv() => { match osc_msg {
                     osc::OscEvent::Message(message) => {
-                        lua_sender.send(LuaEngineEvent::OscReceived(
-                            message.addr,
-                            serde_json::Value::Array(message.args.iter().map(osc_to_json).collect()),
-                        )).unwrap();
+                        if let Some(id) = reported_avatar(&message) {
+                            avatar_reported(&app_handle, &lua_sender, &mut switches, id);
+                        }
+                        let changed_only = settings.borrow().osc.changed_only;
+                        links.receive(message, changed_only, &lua_sender);
+                    }
+                    osc::OscEvent::ClientConnected(name, addr) => {
+                        send_lua(&lua_sender, LuaEngineEvent::ClientConnected(name, addr.to_string()));
+                    }
+                    osc::OscEvent::ClientDisconnected(name) => {
+                        send_lua(&lua_sender, LuaEngineEvent::ClientDisconnected(name));
+                    }
+                    osc::OscEvent::SendError(target, addr, error) => {
+                        send_lua(&lua_sender, LuaEngineEvent::SendError(target, addr, error));
+                    }
+                    osc::OscEvent::Health(health) => {
+                        *app_handle
+                            .state::<Mutex<AppState>>()
+                            .lock()
+                            .expect("state.")
+                            .osc_health
+                            .lock()
+                            .expect("state.osc_health") = health;
+                        reload_menu(&app_handle);
+                    }
+                    osc::OscEvent::Bundle(bundle) => {
+                        let changed_only = settings.borrow().osc.changed_only;
+                        for message in osc::unpack(rosc::OscPacket::Bundle(bundle)) {
+                            if let Some(id) = reported_avatar(&message) {
+                                avatar_reported(&app_handle, &lua_sender, &mut switches, id);
+                            }
+                            links.receive(message, changed_only, &lua_sender);
+                        }
                     }
                 } },
-                _ = tokio::time::sleep(tokio::time::Duration::from_millis(10)) => {},
+                _ = tokio::time::sleep_until(
+                    deadline.map_or_else(tokio::time::Instant::now, tokio::time::Instant::from_std),
+                ), if deadline.is_some() => {
+                    let current = links.current_avatar();
+                    let now = std::time::Instant::now();
+                    if let Some(id) = avatar_guard.poll(current.as_deref(), cooldown, now) {
+                        info!("avatar change to {} after cooldown", id);
+                        let previous = switches.sent(&id, now);
+                        report_switch_result(&app_handle, &lua_sender, previous);
+                        let message = rosc::OscMessage {
+                            addr: AVATAR_CHANGE.to_string(),
+                            args: vec![rosc::OscType::String(id)],
+                        };
+                        links.send_message(osc::VRCHAT_TARGET.to_string(), message).await;
+                    }
+                },
+                _ = tokio::time::sleep_until(
+                    switch_deadline.map_or_else(tokio::time::Instant::now, tokio::time::Instant::from_std),
+                ), if switch_deadline.is_some() => {
+                    let result = switches.poll(timeout, std::time::Instant::now());
+                    report_switch_result(&app_handle, &lua_sender, result);
+                },
                 else => {
                     warn!("channel is closed");
+                    break;
                 },
             }
         }
     });
 }
 
-fn auto_update(app: &AppHandle) {
+fn auto_update(app: &AppHandle, settings: &Settings) {
     let version = app.package_info().version.clone();
+    let update_settings = settings.update.clone();
+    let app = app.clone();
     info!("update check {}", version);
     tauri::async_runtime::spawn(async move {
-        let update_result = update::check_for_updates(version).await;
+        let cache_path = update_cache_path(&app);
+        let mut cache = update::UpdateCache::load(&cache_path);
+        let update_result =
+            update::check_for_updates(version, &update_settings, &mut cache, chrono::Utc::now())
+                .await;
+        if let Err(e) = cache.save(&cache_path) {
+            warn!("could not save update cache: {}", e);
+        }
         match update_result {
-            Ok(Some(url)) => {
-                info!("open url: {}", url);
-                if tauri_plugin_opener::open_url(&url, None::<&str>).is_err() {
-                    error!("open url failed. {}", url);
+            Ok(Some(info)) => {
+                let update_settings = app
+                    .state::<Mutex<AppState>>()
+                    .lock()
+                    .expect("state.")
+                    .settings
+                    .borrow()
+                    .update
+                    .clone();
+                if update::should_notify(&update_settings, &info, chrono::Utc::now()) {
+                    if update_settings.auto_install && info.installer_url.is_some() {
+                        install_update(&app, info.clone());
+                    }
+                    notify_update(&app, Some(info));
+                } else {
+                    info!("update {} is skipped or postponed", info.version);
                 }
             }
+            Err(e) if e.is_quiet() => {
+                info!("update check skipped: {}", e);
+            }
             Err(e) => {
                 error!("{}", e);
             }
@@ -411,6 +1046,213 @@ fn auto_update(app: &AppHandle) {
     });
 }
 
+/// Shows (or with `None`, clears) the update in the tray and tells the frontend.
+fn notify_update(app: &AppHandle, info: Option<update::UpdateInfo>) {
+    info!(
+        "update notification: {:?}",
+        info.as_ref().map(|i| &i.version)
+    );
+    let state = app.state::<Mutex<AppState>>();
+    let icon = {
+        let state = state.lock().expect("state.");
+        *state.update.lock().expect("state.update") = info.clone();
+        state.tray_icon.lock().expect("state.tray_icon").clone()
+    };
+    if let Some(icon) = icon {
+        let tooltip = match &info {
+            Some(info) => format!("OscWardrobe (update {} available)", info.version),
+            None => "OscWardrobe".to_string(),
+        };
+        if let Err(e) = icon.set_tooltip(Some(tooltip)) {
+            warn!("set tooltip: {:?}", e);
+        }
+    }
+    reload_menu(app);
+    if let Err(e) = app.emit("update-available", &info) {
+        warn!("emit update-available: {:?}", e);
+    }
+}
+
+/// `osc.vrchatOscDir` in settings, and [`AppState::avatar_configs`]
+fn avatar_configs_state(
+    app: &AppHandle,
+) -> (
+    Option<PathBuf>,
+    Arc<Mutex<BTreeMap<String, avatar_config::AvatarConfig>>>,
+) {
+    let state = app.state::<Mutex<AppState>>();
+    let state = state.lock().expect("state.");
+    let dir = state.settings.borrow().osc.vrchat_osc_dir();
+    (dir, state.avatar_configs.clone())
+}
+
+/// Reads every VRChat's OSC config, which it writes on avatar load. Blocking; there may be
+/// hundreds of them.
+fn load_avatar_configs(app: &AppHandle, lua_sender: &UnboundedSender<LuaEngineEvent>) {
+    let (dir, _) = avatar_configs_state(app);
+    let configs = match dir {
+        Some(dir) => avatar_config::read_dir(&dir),
+        None => BTreeMap::new(),
+    };
+    update_avatar_configs(app, lua_sender, |current| *current = configs);
+}
+
+/// Reads the OSC config of `id` only, which VRChat has just written if it is new. Blocking.
+fn load_avatar_config(app: &AppHandle, lua_sender: &UnboundedSender<LuaEngineEvent>, id: &str) {
+    let (Some(dir), _) = avatar_configs_state(app) else {
+        return;
+    };
+    let Some(config) = avatar_config::read_avatar(&dir, id) else {
+        return;
+    };
+    update_avatar_configs(app, lua_sender, |current| {
+        current.insert(config.id.clone(), config);
+    });
+}
+
+/// Applies `f` to the stored configs and passes them on if changed.
+fn update_avatar_configs(
+    app: &AppHandle,
+    lua_sender: &UnboundedSender<LuaEngineEvent>,
+    f: impl FnOnce(&mut BTreeMap<String, avatar_config::AvatarConfig>),
+) {
+    let (_, avatar_configs) = avatar_configs_state(app);
+    let configs = {
+        let mut current = avatar_configs.lock().expect("state.avatar_configs");
+        let before = current.clone();
+        f(&mut current);
+        if *current == before {
+            return;
+        }
+        current.clone()
+    };
+    debug!("avatar configs: {}", configs.len());
+    update_catalog(app, lua_sender, |catalog| catalog.merge_configs(&configs));
+    send_lua(
+        lua_sender,
+        LuaEngineEvent::AvatarConfigsUpdated(
+            serde_json::to_value(&configs).expect("avatar configs to json"),
+        ),
+    );
+    if let Err(e) = app.emit("avatar-configs-updated", &configs) {
+        warn!("emit avatar-configs-updated: {:?}", e);
+    }
+}
+
+#[tauri::command]
+fn get_avatar_catalog(
+    state: tauri::State<Mutex<AppState>>,
+) -> BTreeMap<String, avatar_catalog::CatalogEntry> {
+    state
+        .lock()
+        .expect("get AppState")
+        .avatar_catalog
+        .lock()
+        .expect("state.avatar_catalog")
+        .avatars
+        .clone()
+}
+
+#[tauri::command]
+fn get_avatar_configs(
+    state: tauri::State<Mutex<AppState>>,
+) -> BTreeMap<String, avatar_config::AvatarConfig> {
+    state
+        .lock()
+        .expect("get AppState")
+        .avatar_configs
+        .lock()
+        .expect("state.avatar_configs")
+        .clone()
+}
+
+#[tauri::command]
+fn get_update(state: tauri::State<Mutex<AppState>>) -> Option<update::UpdateInfo> {
+    state
+        .lock()
+        .expect("get AppState")
+        .update
+        .lock()
+        .expect("state.update")
+        .clone()
+}
+
+/// `action`: `open`, `download` (installer, or release page if none), `install` (on next start),
+/// `remind_later` or `skip`
+#[tauri::command]
+fn update_action(app: AppHandle, action: &str) -> Result<(), String> {
+    let Some(info) = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("get AppState")
+        .update
+        .lock()
+        .expect("state.update")
+        .clone()
+    else {
+        return Err("no update".to_string());
+    };
+    match action {
+        "open" => {
+            info!("open url: {}", info.url);
+            tauri_plugin_opener::open_url(&info.url, None::<&str>).map_err(|e| e.to_string())?;
+        }
+        "download" => {
+            let url = info.installer_url.as_ref().unwrap_or(&info.url);
+            info!("open url: {}", url);
+            tauri_plugin_opener::open_url(url, None::<&str>).map_err(|e| e.to_string())?;
+        }
+        "remind_later" => {
+            let remind_after = chrono::Utc::now() + chrono::Duration::days(1);
+            settings::modify_settings(&app, |s| {
+                s.update.remind_after = Some(remind_after.to_rfc3339())
+            })?;
+            notify_update(&app, None);
+        }
+        "install" => install_update(&app, info),
+        "skip" => {
+            settings::modify_settings(&app, |s| {
+                s.update.skipped_version = Some(info.version.clone())
+            })?;
+            notify_update(&app, None);
+        }
+        _ => return Err(format!("unknown action: {}", action)),
+    }
+    Ok(())
+}
+
+/// Downloads and checksums the installer in background; it runs on next start.
+/// Tells the frontend with `update-staged` event.
+fn install_update(app: &AppHandle, info: update::UpdateInfo) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let version = app.package_info().version.clone();
+        match update::stage_update(&version, &info, &updates_dir(&app)).await {
+            Ok(staged) => {
+                if let Err(e) = app.emit("update-staged", &staged.version) {
+                    warn!("emit update-staged: {}", e);
+                }
+            }
+            Err(e) => error!("could not stage update {}: {}", info.version, e),
+        }
+    });
+}
+
+/// Runs the installer staged by [`install_update`]. `true` if it runs and the app should exit.
+fn apply_staged_update(app: &AppHandle) -> bool {
+    let Some(staged) = update::take_staged(&updates_dir(app), &app.package_info().version) else {
+        return false;
+    };
+    info!("install update {}: {:?}", staged.version, staged.path);
+    match update::launch_installer(&staged.path) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("could not launch installer: {}", e);
+            false
+        }
+    }
+}
+
 fn lua_dir(app: &AppHandle) -> PathBuf {
     app.path()
         .resolve("lua", BaseDirectory::AppData)
@@ -419,6 +1261,26 @@ fn lua_dir(app: &AppHandle) -> PathBuf {
 fn lua_io_dir(app: &AppHandle) -> PathBuf {
     lua_dir(&app).join("io")
 }
+pub(crate) fn settings_path(app: &AppHandle) -> PathBuf {
+    app.path()
+        .resolve("settings.json", BaseDirectory::AppData)
+        .expect("settings path resolve")
+}
+fn avatar_catalog_path(app: &AppHandle) -> PathBuf {
+    app.path()
+        .resolve("avatar_catalog.json", BaseDirectory::AppData)
+        .expect("avatar catalog path resolve")
+}
+fn update_cache_path(app: &AppHandle) -> PathBuf {
+    app.path()
+        .resolve("update_cache.json", BaseDirectory::AppData)
+        .expect("update cache path resolve")
+}
+fn updates_dir(app: &AppHandle) -> PathBuf {
+    app.path()
+        .resolve("updates", BaseDirectory::AppData)
+        .expect("updates dir resolve")
+}
 fn defs_dir(app: &AppHandle) -> PathBuf {
     app.path()
         .resolve("defs", BaseDirectory::AppData)
@@ -432,12 +1294,94 @@ where
     Ok(())
 }
 
+/// menu item id prefix of "Switch to", followed by the alias
+const SWITCH_TO: &str = "switch_to:";
+
+/// Sends `/avatar/change` like scripts do, so it goes through the same guard.
+fn switch_to(app: &AppHandle, sender: &UnboundedSender<ApplicationEvent>, alias: &str) {
+    let id = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .aliases
+        .lock()
+        .expect("state.aliases")
+        .get(alias)
+        .cloned();
+    let Some(id) = id else {
+        warn!("unknown alias: {}", alias);
+        return;
+    };
+    info!("switch to {} ({}) from the tray menu", alias, id);
+    sender
+        .send(ApplicationEvent::SendOsc(
+            AVATAR_CHANGE.to_string(),
+            vec![rosc::OscType::String(id)],
+        ))
+        .unwrap();
+}
+
+/// menu item id prefix of `wardrobe.menu` items, followed by the item id
+const SCRIPT_MENU: &str = "script_menu:";
+
+fn script_menu_item(
+    app: &AppHandle,
+    item: &script_menu::ScriptMenuItem,
+) -> tauri::Result<MenuItemKind<tauri::Wry>> {
+    use script_menu::ScriptMenuKind;
+    let id = format!("{}{}", SCRIPT_MENU, item.id);
+    Ok(match &item.kind {
+        ScriptMenuKind::Item => MenuItemKind::MenuItem(MenuItem::with_id(
+            app,
+            id,
+            &item.text,
+            item.enabled,
+            None::<&str>,
+        )?),
+        ScriptMenuKind::Check(checked) => MenuItemKind::Check(CheckMenuItem::with_id(
+            app,
+            id,
+            &item.text,
+            item.enabled,
+            *checked,
+            None::<&str>,
+        )?),
+        ScriptMenuKind::Submenu(items) => {
+            let items = items
+                .iter()
+                .map(|item| script_menu_item(app, item))
+                .collect::<tauri::Result<Vec<_>>>()?;
+            let items = items
+                .iter()
+                .map(|item| item as &dyn IsMenuItem<tauri::Wry>)
+                .collect::<Vec<_>>();
+            MenuItemKind::Submenu(Submenu::with_id_and_items(
+                app,
+                id,
+                &item.text,
+                item.enabled,
+                &items,
+            )?)
+        }
+    })
+}
+
 fn build_menu(app: &AppHandle) -> Result<Menu<tauri::Wry>, Box<dyn std::error::Error>> {
+    let aliases = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .aliases
+        .lock()
+        .expect("state.aliases")
+        .clone();
+    let mut switch_menu = SubmenuBuilder::new(app, "Switch to").enabled(!aliases.is_empty());
+    for alias in aliases.keys() {
+        switch_menu = switch_menu.text(format!("{}{}", SWITCH_TO, alias), alias);
+    }
+    let switch_menu = switch_menu.build()?;
     let lua_menu = SubmenuBuilder::new(app, "Lua")
         .text("lua_reload", "Reload")
-        .separator()
-        .separator()
-        .text("menu_reload", "Menu reload")
         .build()?;
     let directory_menu = SubmenuBuilder::new(app, "Open Folder")
         .text("directory_lua", "Lua")
@@ -451,16 +1395,127 @@ fn build_menu(app: &AppHandle) -> Result<Menu<tauri::Wry>, Box<dyn std::error::E
         .build()?;
     let separator = PredefinedMenuItem::separator(app)?;
     let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    let osc_health = *app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .osc_health
+        .lock()
+        .expect("state.osc_health");
+    let osc_health_i = MenuItem::with_id(
+        app,
+        "osc_health",
+        format!("OSC: {}", osc_health),
+        false,
+        None::<&str>,
+    )?;
+    let avatar_switch_failure = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .avatar_switch_failure
+        .lock()
+        .expect("state.avatar_switch_failure")
+        .clone();
 
-    Ok(Menu::with_items(
+    let menu = Menu::with_items(
         app,
-        &[&lua_menu, &directory_menu, &log_menu, &separator, &quit_i],
-    )?)
+        &[
+            &osc_health_i,
+            &PredefinedMenuItem::separator(app)?,
+            &switch_menu,
+            &lua_menu,
+            &directory_menu,
+            &log_menu,
+            &separator,
+            &quit_i,
+        ],
+    )?;
+    let script_items = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .script_menu
+        .lock()
+        .expect("state.script_menu")
+        .iter()
+        .map(|item| script_menu_item(app, item))
+        .collect::<tauri::Result<Vec<_>>>()?;
+    if !script_items.is_empty() {
+        let script_separator = PredefinedMenuItem::separator(app)?;
+        let mut items = script_items
+            .iter()
+            .map(|item| item as &dyn IsMenuItem<tauri::Wry>)
+            .collect::<Vec<_>>();
+        items.push(&script_separator);
+        menu.insert_items(&items, 2)?;
+    }
+    if let Some(failure) = avatar_switch_failure {
+        let failure_i = MenuItem::with_id(
+            app,
+            "avatar_switch_failure",
+            format!("Avatar switch failed: {}", failure),
+            false,
+            None::<&str>,
+        )?;
+        menu.insert(&failure_i, 1)?;
+    }
+    let update = app
+        .state::<Mutex<AppState>>()
+        .lock()
+        .expect("state.")
+        .update
+        .lock()
+        .expect("state.update")
+        .clone();
+    if let Some(update) = update {
+        let mut update_menu =
+            SubmenuBuilder::new(app, format!("Update {} available", update.version))
+                .text("update_open", "Open release page");
+        if update.installer_url.is_some() {
+            update_menu = update_menu.text("update_install", "Install on next start");
+        }
+        let update_menu = update_menu
+            .text("update_remind_later", "Remind me later")
+            .text("update_skip", "Skip this version")
+            .build()?;
+        menu.prepend_items(&[&update_menu, &PredefinedMenuItem::separator(app)?])?;
+    }
+    Ok(menu)
+}
+
+/// Rebuilds the tray menu. Errors are logged rather than panicking the callers, e.g. the event
+/// processor.
+fn reload_menu(app: &AppHandle) {
+    let state = app.state::<Mutex<AppState>>();
+    let Some(icon) = state
+        .lock()
+        .expect("state.")
+        .tray_icon
+        .lock()
+        .expect("state.tray_icon")
+        .clone()
+    else {
+        debug!("icon is None");
+        return;
+    };
+    let menu = match build_menu(app) {
+        Ok(menu) => menu,
+        Err(e) => {
+            warn!("could not build menu: {}", e);
+            return;
+        }
+    };
+    match icon.set_menu(Some(menu)) {
+        Ok(()) => debug!("menu reload done"),
+        Err(e) => warn!("could not set menu: {}", e),
+    }
 }
 
 fn setup_tray_menu(
     app: &mut App,
-    tx: Sender<ApplicationEvent>,
+    tx: UnboundedSender<ApplicationEvent>,
+    lua_sender: UnboundedSender<LuaEngineEvent>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let sender_ = tx.clone();
     let menu = build_menu(app.app_handle())?;
@@ -501,23 +1556,17 @@ fn setup_tray_menu(
                     let _ = log_window.set_focus();
                 }
             }
-            "menu_reload" => {
-                let state = app.state::<Mutex<AppState>>();
-                let Some(icon) = state
-                    .lock()
-                    .expect("state.")
-                    .tray_icon
-                    .lock()
-                    .expect("state.tray_icon")
-                    .clone()
-                else {
-                    debug!("icon is None");
-                    return;
-                };
-                icon.set_menu(Some(build_menu(app.app_handle()).expect("build_menu")))
-                    .expect("set menu on reload");
-                debug!("menu reload done");
+            "update_open" | "update_install" | "update_remind_later" | "update_skip" => {
+                let action = event.id.as_ref().trim_start_matches("update_");
+                if let Err(e) = update_action(app.clone(), action) {
+                    warn!("update action {}: {}", action, e);
+                }
             }
+            id if id.starts_with(SWITCH_TO) => switch_to(app, &sender_, &id[SWITCH_TO.len()..]),
+            id if id.starts_with(SCRIPT_MENU) => match id[SCRIPT_MENU.len()..].parse() {
+                Ok(id) => send_lua(&lua_sender, LuaEngineEvent::MenuClicked(id)),
+                Err(e) => warn!("invalid menu id {}: {}", id, e),
+            },
             _ => (),
         })
         .on_tray_icon_event(|tray, event| {
@@ -2,10 +2,11 @@ use crate::AppState;
 use regex::Regex;
 use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::mpsc::channel;
 use std::sync::LazyLock;
 use std::sync::{Arc, Mutex};
 use tauri::ipc::Channel;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase", tag = "event", content = "data")]
@@ -19,7 +20,7 @@ pub enum LogEvent {
 }
 
 pub struct LogState {
-    pub sender: std::sync::mpsc::Sender<String>,
+    pub sender: UnboundedSender<String>,
     map: HashMap<String, Channel<LogEvent>>,
 }
 
@@ -52,8 +53,8 @@ fn get_target_name(str: &String) -> Option<&str> {
 }
 
 impl LogState {
-    pub fn create() -> (LogState, std::sync::mpsc::Receiver<String>) {
-        let (tx, rx) = channel();
+    pub fn create() -> (LogState, UnboundedReceiver<String>) {
+        let (tx, rx) = unbounded_channel();
         (
             LogState {
                 sender: tx,
@@ -63,6 +64,14 @@ impl LogState {
         )
     }
 
+    /// fern output which forwards formatted lines to [`LogState::process`]
+    pub fn output(&self) -> fern::Output {
+        let sender = self.sender.clone();
+        fern::Output::call(move |record| {
+            let _ = sender.send(record.args().to_string());
+        })
+    }
+
     pub fn print_to_log(&self, line: &str) {
         let event = LogEvent::Print {
             line: line.to_string(),
@@ -75,41 +84,49 @@ impl LogState {
         }
     }
 
-    fn process_print(&self, receiver: &std::sync::mpsc::Receiver<String>) {
-        loop {
-            let Ok(str) = receiver.try_recv() else {
-                break;
-            };
-            self.print_to_log(&str);
+    fn log_to_log(&self, line: String) {
+        if let Some(target) = get_target_name(&line) {
+            if let Some(channel) = self.map.get(target) {
+                channel.send(LogEvent::Log { line: line.clone() }).unwrap();
+            }
+        }
+        if let Some(channel) = self.map.get("all") {
+            channel.send(LogEvent::Log { line }).unwrap();
         }
     }
 
     pub async fn process(
         state: Arc<Mutex<LogState>>,
-        receiver: std::sync::mpsc::Receiver<String>,
-        print_receiver: Option<std::sync::mpsc::Receiver<String>>,
+        mut receiver: UnboundedReceiver<String>,
+        mut print_receiver: Option<UnboundedReceiver<String>>,
+        mut shutdown: watch::Receiver<bool>,
     ) {
         loop {
-            tokio::task::yield_now().await;
-
-            if let Some(print_receiver) = &print_receiver {
-                state
-                    .lock()
-                    .expect("process.state")
-                    .process_print(print_receiver);
-            }
-
-            if let Ok(str) = receiver.recv() {
-                if let Some(target) = get_target_name(&str) {
+            tokio::select! {
+                Some(line) = receiver.recv() => {
+                    state.lock().expect("process.state").log_to_log(line);
+                },
+                Some(line) = async {
+                    match print_receiver.as_mut() {
+                        Some(print_receiver) => print_receiver.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    state.lock().expect("process.state").print_to_log(&line);
+                },
+                _ = shutdown.wait_for(|stop| *stop) => {
                     let state = state.lock().expect("process.state");
-                    if let Some(channel) = state.map.get(target) {
-                        channel.send(LogEvent::Log { line: str.clone() }).unwrap();
+                    if let Some(print_receiver) = print_receiver.as_mut() {
+                        while let Ok(line) = print_receiver.try_recv() {
+                            state.print_to_log(&line);
+                        }
+                    }
+                    while let Ok(line) = receiver.try_recv() {
+                        state.log_to_log(line);
                     }
-                }
-                let state = state.lock().expect("process.state");
-                if let Some(channel) = state.map.get("all") {
-                    channel.send(LogEvent::Log { line: str }).unwrap();
-                }
+                    break;
+                },
+                else => break,
             }
         }
     }
@@ -1,4 +1,13 @@
+//! Lua scripting engine, usable without Tauri.
+//!
+//! A [`LuaEngine`] loads `main.lua` from [`LuaEngineOption::base_dir`], takes [`LuaEngineEvent`]s
+//! as input, and emits what scripts do (`osc.send`, `wardrobe.exit`, `print`) to [`Sink`]s.
+//! `Lua` is not `Send`, so the engine must stay on the thread that created it.
+
 use crate::application_event::ApplicationEvent;
+use crate::osc_query::{Access, EndpointType, OscEndpoint};
+use crate::script_menu::{ScriptMenu, ScriptMenuItem, ScriptMenuKind};
+pub use crate::settings::LuaSettings;
 use fs_extra;
 use log::{debug, trace, warn};
 use mlua::jail::{GetEnvOption, JailOptions, OsClockOption, PackageLibOption};
@@ -6,97 +15,586 @@ use mlua::prelude::{LuaMultiValue, LuaResult};
 use mlua::{IntoLuaMulti, Lua, LuaSerdeExt, MultiValue, Table};
 use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Destination of engine output.
+///
+/// Implemented for `std` and `tokio` channel senders; implement it for your own type to route
+/// OSC messages or logs anywhere else.
+pub trait Sink<T>: Send + Sync {
+    fn send(&self, value: T);
+}
+
+impl<T: Send> Sink<T> for std::sync::mpsc::Sender<T> {
+    fn send(&self, value: T) {
+        if std::sync::mpsc::Sender::send(self, value).is_err() {
+            warn!("sink is closed");
+        }
+    }
+}
+
+impl<T: Send> Sink<T> for tokio::sync::mpsc::UnboundedSender<T> {
+    fn send(&self, value: T) {
+        if tokio::sync::mpsc::UnboundedSender::send(self, value).is_err() {
+            warn!("sink is closed");
+        }
+    }
+}
 
 pub struct LuaEngineOption {
-    pub application_event_sender: Sender<ApplicationEvent>,
-    pub print_sender: Option<Sender<String>>,
-    pub lua_engine_event_receiver: Receiver<LuaEngineEvent>,
+    /// receives `osc.send` as [`ApplicationEvent::SendOsc`], `osc.send_to` as
+    /// [`ApplicationEvent::SendOscTo`], `osc.select_client` as
+    /// [`ApplicationEvent::SelectOscClient`], `osc.bundle` as
+    /// [`ApplicationEvent::SendOscBundle`], `osc.endpoint` as
+    /// [`ApplicationEvent::SetOscEndpoints`], `wardrobe.menu` as
+    /// [`ApplicationEvent::SetScriptMenu`] and `wardrobe.exit` as [`ApplicationEvent::Exit`]
+    pub application_event_sender: Arc<dyn Sink<ApplicationEvent>>,
+    /// receives `print` output. Lua's own `print` (stdout) is kept if `None`
+    pub print_sender: Option<Arc<dyn Sink<String>>>,
+    pub lua_engine_event_receiver: UnboundedReceiver<LuaEngineEvent>,
+    /// directory of `main.lua` and modules for `require`
     pub base_dir: PathBuf,
+    /// root directory of `io` library
     pub io_dir: PathBuf,
+    pub settings: LuaSettings,
+    /// kept up to date by the engine; check [`OscFilter::matches`] before sending
+    /// [`LuaEngineEvent::OscReceived`]
+    pub osc_filter: OscFilter,
+    /// latest received values for `osc.get`, updated by whoever receives OSC
+    pub osc_cache: crate::osc_cache::OscCache,
 }
 
 pub struct LuaEngine {
     lua: std::sync::Mutex<Lua>,
     option: LuaEngineOption,
+    /// last [`LuaEngineEvent::DefinitionUpdated`], set again after reload
+    definition: Option<serde_json::Value>,
+    /// `wardrobe.<key>` last set by [`LuaEngineEvent::AvatarConfigsUpdated`] and
+    /// [`LuaEngineEvent::CatalogUpdated`], set again after reload
+    wardrobe_values: std::collections::BTreeMap<&'static str, serde_json::Value>,
+    endpoints: Arc<std::sync::Mutex<Endpoints>>,
+    menu: Arc<std::sync::Mutex<ScriptMenu>>,
 }
 
+/// `osc.endpoint` declarations by address, sent when `changed`
+#[derive(Default)]
+struct Endpoints {
+    declared: std::collections::BTreeMap<String, OscEndpoint>,
+    /// `osc.on` id of the handler declared with each address
+    handlers: std::collections::BTreeMap<String, mlua::Integer>,
+    changed: bool,
+}
+
+/// Input of [`LuaEngine`].
+#[derive(Debug)]
 pub enum LuaEngineEvent {
-    OscReceived(String, serde_json::Value),
+    /// calls global `receive(addr, args)` and `osc.on` handlers of matching patterns.
+    /// args become a sequence table, see [`crate::osc_lua`]
+    OscReceived(String, Vec<rosc::OscType>),
+    /// sets `wardrobe.definition`, see [`crate::definition::get_definition`]
     DefinitionUpdated(serde_json::Value),
+    /// sets `wardrobe.avatar_configs`, VRChat's OSC configs by blueprint ID, see
+    /// [`crate::avatar_config::read_dir`]
+    AvatarConfigsUpdated(serde_json::Value),
+    /// sets `wardrobe.catalog`, known avatars by blueprint ID, see
+    /// [`crate::avatar_catalog::AvatarCatalog`]
+    CatalogUpdated(serde_json::Value),
+    /// calls global `stop()`, recreates the Lua state and runs `main.lua` again
     Reload,
+    /// reloads with new sandbox settings
+    SettingsUpdated(LuaSettings),
+    /// calls global `on_client_connected(name, addr)` if defined. It may return `addr, ...` to
+    /// send to the client as a handshake
+    ClientConnected(String, String),
+    /// calls global `on_client_disconnected(name)` if defined
+    ClientDisconnected(String),
+    /// calls global `on_send_error(target, addr, error)` if defined
+    SendError(String, String, String),
+    /// calls global `on_avatar_switch_result(id, ok, reason)` if defined, after VRChat switches
+    /// to a sent `/avatar/change` or not
+    AvatarSwitchResult(crate::avatar_change::SwitchResult),
+    /// calls `on_click` of the `wardrobe.menu` item with the id, with the new state of a checkbox
+    MenuClicked(i64),
+    /// calls global `stop()` and ends [`LuaEngine::run`]
+    Stop,
+}
+
+/// registry key of the table of `osc.on` handlers by id
+const OSC_HANDLERS: &str = "osc_handlers";
+/// registry key of the table of `wardrobe.menu` `on_click` handlers by item id
+const MENU_HANDLERS: &str = "menu_handlers";
+
+/// Addresses scripts listen to, by global `receive` (everything) and `osc.on` patterns.
+///
+/// Shared with whoever sends [`LuaEngineEvent::OscReceived`], so that other messages can be
+/// dropped before they wake the engine thread.
+#[derive(Clone, Default)]
+pub struct OscFilter(Arc<std::sync::RwLock<OscFilterState>>);
+
+#[derive(Default)]
+struct OscFilterState {
+    receive_all: bool,
+    next_id: mlua::Integer,
+    patterns: std::collections::BTreeMap<mlua::Integer, String>,
+}
+
+impl OscFilter {
+    /// `true` if scripts want `addr`.
+    pub fn matches(&self, addr: &str) -> bool {
+        let state = self.0.read().expect("osc filter");
+        state.receive_all
+            || state
+                .patterns
+                .values()
+                .any(|p| crate::osc_pattern::matches(p, addr))
+    }
+
+    fn is_receive_all(&self) -> bool {
+        self.0.read().expect("osc filter").receive_all
+    }
+
+    fn set_receive_all(&self, receive_all: bool) {
+        self.0.write().expect("osc filter").receive_all = receive_all;
+    }
+
+    /// `osc.on` ids whose pattern matches `addr`, in subscription order
+    fn handler_ids(&self, addr: &str) -> Vec<mlua::Integer> {
+        let state = self.0.read().expect("osc filter");
+        state
+            .patterns
+            .iter()
+            .filter(|(_, p)| crate::osc_pattern::matches(p, addr))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn add(&self, pattern: String) -> mlua::Integer {
+        let mut state = self.0.write().expect("osc filter");
+        state.next_id += 1;
+        let id = state.next_id;
+        state.patterns.insert(id, pattern);
+        id
+    }
+
+    fn remove(&self, id: mlua::Integer) -> bool {
+        self.0
+            .write()
+            .expect("osc filter")
+            .patterns
+            .remove(&id)
+            .is_some()
+    }
+
+    fn clear(&self) {
+        let mut state = self.0.write().expect("osc filter");
+        state.receive_all = false;
+        state.patterns.clear();
+    }
+}
+
+/// `osc.endpoint(addr, {type = "string", access = "readwrite", description = "...", value = ...})`
+fn endpoint_from_lua(lua: &Lua, addr: String, spec: &Table) -> Result<OscEndpoint, String> {
+    crate::osc_query::validate_address(&addr)?;
+    let field = |key: &str| spec.get::<Option<String>>(key).map_err(|e| e.to_string());
+    let Some(r#type) = field("type")? else {
+        return Err("type is required".to_string());
+    };
+    let r#type = EndpointType::parse(&r#type).ok_or(format!("unknown type: {}", r#type))?;
+    let access = match field("access")? {
+        Some(access) => Access::parse(&access).ok_or(format!("unknown access: {}", access))?,
+        None => Access::default(),
+    };
+    let value = match spec
+        .get::<mlua::Value>("value")
+        .map_err(|e| e.to_string())?
+    {
+        mlua::Value::Nil => None,
+        value => {
+            let value = lua
+                .from_value::<serde_json::Value>(value)
+                .map_err(|e| e.to_string())?;
+            if !r#type.accepts(&value) {
+                return Err(format!("value does not match type: {}", value));
+            }
+            Some(value)
+        }
+    };
+    Ok(OscEndpoint {
+        addr,
+        r#type,
+        access,
+        description: field("description")?,
+        value,
+    })
+}
+
+/// `wardrobe.menu.add{text = "...", enabled = true, checked = false, items = {...}, on_click = f}`.
+/// `checked` makes a checkbox and `items` a submenu of the same specs. `on_click` of the item and
+/// its children is collected into `handlers`.
+fn menu_item_from_lua(
+    menu: &mut ScriptMenu,
+    spec: &Table,
+    handlers: &mut Vec<(i64, mlua::Function)>,
+) -> Result<ScriptMenuItem, String> {
+    let err = |e: mlua::Error| e.to_string();
+    let Some(text) = spec.get::<Option<String>>("text").map_err(err)? else {
+        return Err("text is required".to_string());
+    };
+    let enabled = spec.get::<Option<bool>>("enabled").map_err(err)?;
+    let checked = spec.get::<Option<bool>>("checked").map_err(err)?;
+    let items = spec.get::<Option<Table>>("items").map_err(err)?;
+    let on_click = spec
+        .get::<Option<mlua::Function>>("on_click")
+        .map_err(err)?;
+    let id = menu.next_id();
+    let kind = match (checked, items) {
+        (Some(_), Some(_)) => return Err("checked and items are exclusive".to_string()),
+        (Some(checked), None) => ScriptMenuKind::Check(checked),
+        (None, Some(items)) => {
+            if on_click.is_some() {
+                return Err("submenu can't have on_click".to_string());
+            }
+            let items = items
+                .sequence_values::<Table>()
+                .map(|item| menu_item_from_lua(menu, &item.map_err(err)?, handlers))
+                .collect::<Result<Vec<_>, _>>()?;
+            ScriptMenuKind::Submenu(items)
+        }
+        (None, None) => ScriptMenuKind::Item,
+    };
+    if let Some(on_click) = on_click {
+        handlers.push((id, on_click));
+    }
+    Ok(ScriptMenuItem {
+        id,
+        text,
+        enabled: enabled.unwrap_or(true),
+        kind,
+    })
+}
+
+/// Converts arguments of `osc.send` and friends, or describes the first that can't be sent.
+fn osc_args(values: &[mlua::Value]) -> Result<Vec<rosc::OscType>, String> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            crate::osc_lua::from_lua(v).map_err(|e| format!("invalid argument #{}: {}", i + 1, e))
+        })
+        .collect()
 }
 
+/// The timetag of `osc.bundle`: a `{type="time", seconds=, fractional=}` table, or a delay in
+/// seconds from now. `nil` is "immediately".
+fn bundle_time(time: &mlua::Value) -> Result<Option<rosc::OscTime>, String> {
+    let delay = match time {
+        mlua::Value::Nil => return Ok(None),
+        mlua::Value::Table(_) => {
+            return match crate::osc_lua::from_lua(time) {
+                Ok(rosc::OscType::Time(time)) => Ok(Some(time)),
+                _ => Err("invalid time".to_string()),
+            };
+        }
+        mlua::Value::Integer(delay) => *delay as f64,
+        mlua::Value::Number(delay) => *delay,
+        _ => return Err("invalid time".to_string()),
+    };
+    if !delay.is_finite() || delay < 0.0 {
+        return Err("invalid delay".to_string());
+    }
+    std::time::Duration::try_from_secs_f64(delay)
+        .ok()
+        .and_then(|delay| std::time::SystemTime::now().checked_add(delay))
+        .and_then(|time| rosc::OscTime::try_from(time).ok())
+        .map(Some)
+        .ok_or_else(|| "invalid delay".to_string())
+}
+
+/// How long global `stop()` may take on [`LuaEngineEvent::Stop`] and reload.
+pub const STOP_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(3);
+
 impl LuaEngine {
+    /// Creates the Lua state and its libraries. Scripts are not loaded until [`LuaEngine::start`].
     pub fn new(option: LuaEngineOption) -> LuaEngine {
         trace!("LuaEngine::new");
         LuaEngine::jail(&option);
         let engine = LuaEngine {
             lua: std::sync::Mutex::new(Lua::new()),
             option,
+            definition: None,
+            wardrobe_values: Default::default(),
+            endpoints: Default::default(),
+            menu: Default::default(),
         };
         engine.load_libraries();
         engine
     }
+    /// Values from the app are set again even if `main.lua` fails, so that a fixed script
+    /// reloaded later sees them.
     async fn reload(&mut self) -> LuaResult<()> {
+        self.stop().await;
         LuaEngine::jail(&self.option);
         *self.lua.get_mut().expect("get mut") = Lua::new();
         self.load_libraries();
-        self.start().await
+        let started = self.start().await;
+        if let Some(definition) = self.definition.clone() {
+            self.set_global(&["wardrobe", "definition"], definition)?;
+        }
+        for (key, value) in self.wardrobe_values.clone() {
+            self.set_global(&["wardrobe", key], value)?;
+        }
+        started
     }
+    /// Runs `main.lua`, then global `start()` if defined.
     pub async fn start(&self) -> LuaResult<()> {
-        let lua = &self.lua.lock().expect("get lock for start()");
+        let lua = self.lua.lock().expect("get lock for start()");
 
         let main_path = self.option.base_dir.join("main.lua");
         debug!("main.lua exists: {}", main_path.exists());
         let mut main = std::fs::File::open(&main_path)?;
         let mut buffer = String::new();
         main.read_to_string(&mut buffer)?;
-        let _ = lua
-            .load(buffer.as_str())
-            .exec_async()
-            .await
-            .expect("load main file");
+        lua.load(buffer.as_str()).exec_async().await?;
         drop(main);
         if let Ok(start) = lua.globals().get::<mlua::Function>("start") {
             let return_value = start.call_async::<MultiValue>(()).await?;
             debug!("start() returns {:?}", return_value);
         };
+        drop(lua);
+        self.refresh_filter();
+        self.refresh_endpoints();
+        self.refresh_menu();
         Ok(())
     }
 
+    /// Calls global `stop()` if defined, giving up after [`STOP_TIMEOUT`].
+    pub async fn stop(&self) {
+        let stop = {
+            let lua = &self.lua.lock().expect("get lock for stop()");
+            lua.globals().get::<mlua::Function>("stop")
+        };
+        let Ok(stop) = stop else {
+            return;
+        };
+        match tokio::time::timeout(STOP_TIMEOUT, stop.call_async::<()>(())).await {
+            Ok(Ok(())) => debug!("stop() done"),
+            Ok(Err(e)) => warn!("error on stop(): {:?}", e),
+            Err(_) => warn!("stop() timed out"),
+        }
+    }
+
+    /// Handles [`LuaEngineEvent`]s as they arrive, until [`LuaEngineEvent::Stop`] or every sender
+    /// is dropped.
+    pub async fn run(&mut self) {
+        while let Some(event) = self.option.lua_engine_event_receiver.recv().await {
+            let is_stop = matches!(event, LuaEngineEvent::Stop);
+            self.handle_event(event).await;
+            if is_stop {
+                debug!("lua engine stopped");
+                return;
+            }
+        }
+        debug!("lua engine event channel is closed");
+    }
+
+    /// Handles every queued [`LuaEngineEvent`] without waiting and returns how many were handled.
     pub async fn process_event(&mut self) -> i32 {
-        // trace!("LuaEngine::process_event");
         let mut counter = 0;
-        loop {
-            match self.option.lua_engine_event_receiver.try_recv() {
-                Ok(event) => {
-                    match event {
-                        LuaEngineEvent::OscReceived(s, v) => {
-                            let args = {
-                                let lua = self.lua.lock().expect("get lock for receive()");
-                                lua.to_value(&v)
-                            };
-                            if let Err(e) = self.call_function("receive", (s, args)).await {
-                                warn!("error on  Osc receive event: {:?}", e);
-                            };
-                        }
-                        LuaEngineEvent::DefinitionUpdated(v) => {
-                            debug!("Definition updated event: {:?}", v);
-                            if let Err(e) = self.set_global(&["wardrobe", "definition"], v) {
-                                warn!("error on  DefinitionUpdated event: {:?}", e);
-                            };
+        while let Ok(event) = self.option.lua_engine_event_receiver.try_recv() {
+            self.handle_event(event).await;
+            counter += 1;
+        }
+        counter
+    }
+
+    async fn handle_event(&mut self, event: LuaEngineEvent) {
+        self.handle_event_inner(event).await;
+        self.refresh_filter();
+        self.refresh_endpoints();
+        self.refresh_menu();
+    }
+
+    /// Scripts may define or remove global `receive` at any time.
+    fn refresh_filter(&self) {
+        let lua = self.lua.lock().expect("get lock for refresh_filter()");
+        let receive_all = matches!(
+            lua.globals().get::<mlua::Value>("receive"),
+            Ok(mlua::Value::Function(_))
+        );
+        self.option.osc_filter.set_receive_all(receive_all);
+    }
+
+    /// Publishes `osc.endpoint` declarations once per event rather than on every call.
+    fn refresh_endpoints(&self) {
+        let mut endpoints = self.endpoints.lock().expect("endpoints");
+        if endpoints.changed {
+            endpoints.changed = false;
+            self.option
+                .application_event_sender
+                .send(ApplicationEvent::SetOscEndpoints(
+                    endpoints.declared.values().cloned().collect(),
+                ));
+        }
+    }
+
+    /// Publishes `wardrobe.menu` items once per event, like [`LuaEngine::refresh_endpoints`].
+    fn refresh_menu(&self) {
+        let mut menu = self.menu.lock().expect("script menu");
+        if menu.changed {
+            menu.changed = false;
+            self.option
+                .application_event_sender
+                .send(ApplicationEvent::SetScriptMenu(menu.items.clone()));
+        }
+    }
+
+    async fn handle_event_inner(&mut self, event: LuaEngineEvent) {
+        match event {
+            LuaEngineEvent::OscReceived(s, v) => {
+                let (args, handlers) = {
+                    let lua = self.lua.lock().expect("get lock for receive()");
+                    let handlers = lua
+                        .named_registry_value::<Table>(OSC_HANDLERS)
+                        .expect("osc handlers");
+                    let handlers = self
+                        .option
+                        .osc_filter
+                        .handler_ids(&s)
+                        .into_iter()
+                        .filter_map(|id| handlers.raw_get::<Option<mlua::Function>>(id).ok()?)
+                        .collect::<Vec<_>>();
+                    (crate::osc_lua::to_lua_table(&lua, &v), handlers)
+                };
+                let args = match args {
+                    Ok(args) => args,
+                    Err(e) => {
+                        warn!("could not convert OSC args of {}: {:?}", s, e);
+                        return;
+                    }
+                };
+                if self.option.osc_filter.is_receive_all() {
+                    if let Err(e) = self
+                        .call_function("receive", (s.clone(), args.clone()))
+                        .await
+                    {
+                        warn!("error on  Osc receive event: {:?}", e);
+                    };
+                }
+                for handler in handlers {
+                    if let Err(e) = handler.call_async::<()>((s.clone(), args.clone())).await {
+                        warn!("error on osc.on handler for {}: {:?}", s, e);
+                    }
+                }
+            }
+            LuaEngineEvent::DefinitionUpdated(v) => {
+                debug!("Definition updated event: {:?}", v);
+                self.definition = Some(v.clone());
+                if let Err(e) = self.set_global(&["wardrobe", "definition"], v) {
+                    warn!("error on  DefinitionUpdated event: {:?}", e);
+                };
+            }
+            LuaEngineEvent::AvatarConfigsUpdated(v) => self.set_wardrobe_value("avatar_configs", v),
+            LuaEngineEvent::CatalogUpdated(v) => self.set_wardrobe_value("catalog", v),
+            LuaEngineEvent::Reload => {
+                if let Err(e) = self.reload().await {
+                    warn!("error on reload: {:?}", e);
+                }
+            }
+            LuaEngineEvent::SettingsUpdated(settings) => {
+                if settings != self.option.settings {
+                    debug!("Lua settings updated, reload: {:?}", settings);
+                    self.option.settings = settings;
+                    if let Err(e) = self.reload().await {
+                        warn!("error on reload: {:?}", e);
+                    }
+                }
+            }
+            LuaEngineEvent::ClientConnected(name, addr) => {
+                match self
+                    .call_callback("on_client_connected", (name.clone(), addr))
+                    .await
+                {
+                    // returned `addr, ...` is a handshake to the client
+                    Ok(values) => {
+                        if let Some(mlua::Value::String(addr)) = values.get(0) {
+                            let addr = addr.to_string_lossy();
+                            match osc_args(&values.into_vec()[1..]) {
+                                Ok(args) => self
+                                    .option
+                                    .application_event_sender
+                                    .send(ApplicationEvent::SendOscToClient(name, addr, args)),
+                                Err(e) => {
+                                    warn!("invalid handshake of on_client_connected: {}", e)
+                                }
+                            }
                         }
-                        LuaEngineEvent::Reload => self.reload().await.expect("reload"),
                     }
-                    counter += 1;
+                    Err(e) => warn!("error on on_client_connected: {:?}", e),
                 }
-                _ => {
-                    break;
+            }
+            LuaEngineEvent::ClientDisconnected(name) => {
+                if let Err(e) = self.call_callback("on_client_disconnected", name).await {
+                    warn!("error on on_client_disconnected: {:?}", e);
+                }
+            }
+            LuaEngineEvent::SendError(target, addr, error) => {
+                if let Err(e) = self
+                    .call_callback("on_send_error", (target, addr, error))
+                    .await
+                {
+                    warn!("error on on_send_error: {:?}", e);
                 }
             }
+            LuaEngineEvent::AvatarSwitchResult(result) => {
+                if let Err(e) = self
+                    .call_callback(
+                        "on_avatar_switch_result",
+                        (result.id, result.ok, result.reason),
+                    )
+                    .await
+                {
+                    warn!("error on on_avatar_switch_result: {:?}", e);
+                }
+            }
+            LuaEngineEvent::MenuClicked(id) => {
+                let Some(checked) = self.menu.lock().expect("script menu").click(id) else {
+                    debug!("menu item {} is gone or disabled", id);
+                    return;
+                };
+                let handler = {
+                    let lua = self.lua.lock().expect("get lock for on_click");
+                    lua.named_registry_value::<Table>(MENU_HANDLERS)
+                        .and_then(|handlers| handlers.raw_get::<Option<mlua::Function>>(id))
+                };
+                match handler {
+                    Ok(Some(handler)) => {
+                        if let Err(e) = handler.call_async::<()>(checked).await {
+                            warn!("error on wardrobe.menu on_click of {}: {:?}", id, e);
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(e) => warn!("error on menu handlers: {:?}", e),
+                }
+            }
+            LuaEngineEvent::Stop => self.stop().await,
+        }
+    }
+
+    /// Like `call_function` but returns the results, and does nothing if scripts don't define it.
+    async fn call_callback(
+        &self,
+        function_name: &str,
+        args: impl IntoLuaMulti,
+    ) -> mlua::Result<MultiValue> {
+        let f = {
+            let lua = &self.lua.lock().expect("get lock for call_callback()");
+            lua.globals().get::<Option<mlua::Function>>(function_name)?
+        };
+        match f {
+            Some(f) => f.call_async::<MultiValue>(args).await,
+            None => Ok(MultiValue::new()),
         }
-        counter
     }
 
     async fn call_function(
@@ -112,6 +610,14 @@ impl LuaEngine {
         Ok(())
     }
 
+    /// Sets `wardrobe.<key>`, kept across reloads.
+    fn set_wardrobe_value(&mut self, key: &'static str, value: serde_json::Value) {
+        self.wardrobe_values.insert(key, value.clone());
+        if let Err(e) = self.set_global(&["wardrobe", key], value) {
+            warn!("error on setting wardrobe.{}: {:?}", key, e);
+        }
+    }
+
     fn set_global(&self, keys: &[&str], value: serde_json::Value) -> mlua::Result<()> {
         let (last_key, keys) = keys.split_last().expect("keys must be non-empty");
         let lua = &self.lua.lock().expect("get lock for set_global()");
@@ -144,6 +650,123 @@ impl LuaEngine {
 
         /* ### osc library ### */
         let osc_lib = lua.create_table().expect("create_table osc_lib");
+        let filter = self.option.osc_filter.clone();
+        filter.clear();
+        lua.set_named_registry_value(OSC_HANDLERS, lua.create_table().expect("create_table"))
+            .expect("osc handlers");
+        osc_lib
+            .set(
+                "on",
+                lua.create_function(move |lua, (pattern, f): (String, mlua::Function)| {
+                    if let Err(e) = crate::osc_pattern::validate(&pattern) {
+                        return Ok([lua.null(), mlua::Value::String(lua.create_string(e)?)]);
+                    }
+                    let id = filter.add(pattern);
+                    lua.named_registry_value::<Table>(OSC_HANDLERS)?
+                        .raw_set(id, f)?;
+                    Ok([mlua::Value::Integer(id), lua.null()])
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.on =");
+        let filter = self.option.osc_filter.clone();
+        let endpoints = self.endpoints.clone();
+        {
+            let mut endpoints = endpoints.lock().expect("endpoints");
+            endpoints.changed = !endpoints.declared.is_empty();
+            endpoints.declared.clear();
+            endpoints.handlers.clear();
+        }
+        osc_lib
+            .set(
+                "endpoint",
+                lua.create_function(
+                    move |lua, (addr, spec, f): (String, Table, Option<mlua::Function>)| {
+                        let endpoint = match endpoint_from_lua(lua, addr, &spec) {
+                            Ok(endpoint) => endpoint,
+                            Err(e) => {
+                                return Ok([
+                                    lua.null(),
+                                    mlua::Value::String(lua.create_string(e)?),
+                                ]);
+                            }
+                        };
+                        let mut endpoints = endpoints.lock().expect("endpoints");
+                        // declaring the address again replaces its handler, or keeps it
+                        let id = match f {
+                            Some(f) => {
+                                let handlers = lua.named_registry_value::<Table>(OSC_HANDLERS)?;
+                                if let Some(old) = endpoints.handlers.remove(&endpoint.addr) {
+                                    filter.remove(old);
+                                    handlers.raw_set(old, mlua::Value::Nil)?;
+                                }
+                                let id = filter.add(endpoint.addr.clone());
+                                handlers.raw_set(id, f)?;
+                                endpoints.handlers.insert(endpoint.addr.clone(), id);
+                                mlua::Value::Integer(id)
+                            }
+                            None => match endpoints.handlers.get(&endpoint.addr) {
+                                Some(id) => mlua::Value::Integer(*id),
+                                None => mlua::Value::Boolean(true),
+                            },
+                        };
+                        if endpoints.declared.get(&endpoint.addr) != Some(&endpoint) {
+                            endpoints.declared.insert(endpoint.addr.clone(), endpoint);
+                            endpoints.changed = true;
+                        }
+                        Ok([id, lua.null()])
+                    },
+                )
+                .expect("create_function"),
+            )
+            .expect("osc.endpoint =");
+        let filter = self.option.osc_filter.clone();
+        let endpoints = self.endpoints.clone();
+        osc_lib
+            .set(
+                "off",
+                lua.create_function(move |lua, id: mlua::Integer| {
+                    lua.named_registry_value::<Table>(OSC_HANDLERS)?
+                        .raw_set(id, mlua::Value::Nil)?;
+                    // the handler of `osc.endpoint` takes its endpoint with it
+                    let mut endpoints = endpoints.lock().expect("endpoints");
+                    let addr = endpoints
+                        .handlers
+                        .iter()
+                        .find(|(_, handler)| **handler == id)
+                        .map(|(addr, _)| addr.clone());
+                    if let Some(addr) = addr {
+                        endpoints.handlers.remove(&addr);
+                        endpoints.declared.remove(&addr);
+                        endpoints.changed = true;
+                    }
+                    Ok(filter.remove(id))
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.off =");
+        let cache = self.option.osc_cache.clone();
+        osc_lib
+            .set(
+                "get",
+                lua.create_function(move |lua, addr: String| {
+                    let Some(value) = cache.get(&addr) else {
+                        return Ok((mlua::Value::Nil, mlua::Value::Nil, mlua::Value::Nil));
+                    };
+                    let seconds = |t: std::time::SystemTime| {
+                        t.duration_since(std::time::UNIX_EPOCH)
+                            .map(|d| mlua::Value::Number(d.as_secs_f64()))
+                            .unwrap_or(mlua::Value::Nil)
+                    };
+                    Ok((
+                        mlua::Value::Table(crate::osc_lua::to_lua_table(lua, &value.args)?),
+                        seconds(value.received_at),
+                        seconds(value.changed_at),
+                    ))
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.get =");
         let sender = self.option.application_event_sender.clone();
         osc_lib
             .set(
@@ -163,17 +786,97 @@ impl LuaEngine {
                             ),
                         ]);
                     };
-                    sender
-                        .send(ApplicationEvent::SendOsc(
-                            addr.to_string_lossy(),
-                            serde_json::to_value(&args.into_vec()[1..]).unwrap(),
-                        ))
-                        .expect("application event send osc");
+                    let addr = addr.to_string_lossy();
+                    let args = match osc_args(&args.into_vec()[1..]) {
+                        Ok(args) => args,
+                        Err(e) => {
+                            return Ok([lua.null(), mlua::Value::String(lua.create_string(e)?)]);
+                        }
+                    };
+                    sender.send(ApplicationEvent::SendOsc(addr, args));
                     Ok([mlua::Value::Boolean(true), lua.null()])
                 })
                 .expect("create_function"),
             )
             .expect("osc.send =");
+        let sender = self.option.application_event_sender.clone();
+        osc_lib
+            .set(
+                "send_to",
+                lua.create_function(move |lua, args: LuaMultiValue| {
+                    let (Some(mlua::Value::String(target)), Some(mlua::Value::String(addr))) =
+                        (args.get(0), args.get(1))
+                    else {
+                        return Ok([
+                            lua.null(),
+                            mlua::Value::String(
+                                lua.create_string("target and address are not string")?,
+                            ),
+                        ]);
+                    };
+                    let (target, addr) = (target.to_string_lossy(), addr.to_string_lossy());
+                    let args = match osc_args(&args.into_vec()[2..]) {
+                        Ok(args) => args,
+                        Err(e) => {
+                            return Ok([lua.null(), mlua::Value::String(lua.create_string(e)?)]);
+                        }
+                    };
+                    sender.send(ApplicationEvent::SendOscTo(target, addr, args));
+                    Ok([mlua::Value::Boolean(true), lua.null()])
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.send_to =");
+        let sender = self.option.application_event_sender.clone();
+        osc_lib
+            .set(
+                "select_client",
+                lua.create_function(move |_, name: Option<String>| {
+                    sender.send(ApplicationEvent::SelectOscClient(name));
+                    Ok(())
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.select_client =");
+        let sender = self.option.application_event_sender.clone();
+        osc_lib
+            .set(
+                "bundle",
+                lua.create_function(move |lua, (messages, time): (Table, mlua::Value)| {
+                    let mut bundle = vec![];
+                    for message in messages.sequence_values::<Table>() {
+                        let values = message?
+                            .sequence_values::<mlua::Value>()
+                            .collect::<LuaResult<Vec<_>>>()?;
+                        let Some(mlua::Value::String(addr)) = values.first() else {
+                            return Ok([
+                                lua.null(),
+                                mlua::Value::String(lua.create_string("address is not string")?),
+                            ]);
+                        };
+                        let args = match osc_args(&values[1..]) {
+                            Ok(args) => args,
+                            Err(e) => {
+                                return Ok([
+                                    lua.null(),
+                                    mlua::Value::String(lua.create_string(e)?),
+                                ]);
+                            }
+                        };
+                        bundle.push((addr.to_string_lossy(), args));
+                    }
+                    let time = match bundle_time(&time) {
+                        Ok(time) => time,
+                        Err(e) => {
+                            return Ok([lua.null(), mlua::Value::String(lua.create_string(e)?)]);
+                        }
+                    };
+                    sender.send(ApplicationEvent::SendOscBundle(bundle, time));
+                    Ok([mlua::Value::Boolean(true), lua.null()])
+                })
+                .expect("create_function"),
+            )
+            .expect("osc.bundle =");
         package_loaded.set("osc", &osc_lib).expect("osc");
         lua.globals().set("osc", osc_lib).expect("osc");
 
@@ -184,14 +887,107 @@ impl LuaEngine {
             .set(
                 "exit",
                 lua.create_function(move |_, _: LuaMultiValue| {
-                    sender
-                        .send(ApplicationEvent::Exit)
-                        .expect("application event exit");
+                    sender.send(ApplicationEvent::Exit);
                     Ok([mlua::Value::Nil])
                 })
                 .expect("create_function"),
             )
             .expect("wardrobe.exit =");
+
+        let menu_lib = lua.create_table().expect("create_table menu_lib");
+        self.menu.lock().expect("script menu").clear();
+        lua.set_named_registry_value(MENU_HANDLERS, lua.create_table().expect("create_table"))
+            .expect("menu handlers");
+        let menu = self.menu.clone();
+        menu_lib
+            .set(
+                "add",
+                lua.create_function(move |lua, spec: Table| {
+                    let mut handlers = vec![];
+                    let mut menu = menu.lock().expect("script menu");
+                    let item = match menu_item_from_lua(&mut menu, &spec, &mut handlers) {
+                        Ok(item) => item,
+                        Err(e) => {
+                            return Ok([lua.null(), mlua::Value::String(lua.create_string(e)?)]);
+                        }
+                    };
+                    let registry = lua.named_registry_value::<Table>(MENU_HANDLERS)?;
+                    for (id, on_click) in handlers {
+                        registry.raw_set(id, on_click)?;
+                    }
+                    let id = item.id;
+                    menu.add(item);
+                    Ok([mlua::Value::Integer(id), lua.null()])
+                })
+                .expect("create_function"),
+            )
+            .expect("wardrobe.menu.add =");
+        let menu = self.menu.clone();
+        menu_lib
+            .set(
+                "set",
+                lua.create_function(move |lua, (id, fields): (mlua::Integer, Table)| {
+                    let text = fields.get::<Option<String>>("text")?;
+                    let enabled = fields.get::<Option<bool>>("enabled")?;
+                    let checked = fields.get::<Option<bool>>("checked")?;
+                    let mut menu = menu.lock().expect("script menu");
+                    let Some(item) = menu.find_mut(id) else {
+                        return Ok([
+                            lua.null(),
+                            mlua::Value::String(lua.create_string("no such menu item")?),
+                        ]);
+                    };
+                    match (&mut item.kind, checked) {
+                        (ScriptMenuKind::Check(current), Some(checked)) => *current = checked,
+                        (_, Some(_)) => {
+                            return Ok([
+                                lua.null(),
+                                mlua::Value::String(lua.create_string("not a checkbox")?),
+                            ]);
+                        }
+                        _ => (),
+                    }
+                    if let Some(text) = text {
+                        item.text = text;
+                    }
+                    if let Some(enabled) = enabled {
+                        item.enabled = enabled;
+                    }
+                    menu.changed = true;
+                    Ok([mlua::Value::Boolean(true), lua.null()])
+                })
+                .expect("create_function"),
+            )
+            .expect("wardrobe.menu.set =");
+        let menu = self.menu.clone();
+        menu_lib
+            .set(
+                "remove",
+                lua.create_function(move |lua, id: mlua::Integer| {
+                    let Some(item) = menu.lock().expect("script menu").remove(id) else {
+                        return Ok(false);
+                    };
+                    let registry = lua.named_registry_value::<Table>(MENU_HANDLERS)?;
+                    for id in item.ids() {
+                        registry.raw_set(id, mlua::Value::Nil)?;
+                    }
+                    Ok(true)
+                })
+                .expect("create_function"),
+            )
+            .expect("wardrobe.menu.remove =");
+        let menu = self.menu.clone();
+        menu_lib
+            .set(
+                "clear",
+                lua.create_function(move |lua, ()| {
+                    menu.lock().expect("script menu").clear();
+                    lua.set_named_registry_value(MENU_HANDLERS, lua.create_table()?)
+                })
+                .expect("create_function"),
+            )
+            .expect("wardrobe.menu.clear =");
+        wardrobe_lib.set("menu", menu_lib).expect("wardrobe.menu =");
         package_loaded
             .set("wardrobe", &wardrobe_lib)
             .expect("wardrobe");
@@ -219,7 +1015,7 @@ impl LuaEngine {
                         .collect::<Vec<String>>()
                         .join("\t");
                     println!("{}", s);
-                    print_sender.send(s).expect("print");
+                    print_sender.send(s);
                     return Ok(0);
                 })
                 .expect("create_function");
@@ -228,12 +1024,24 @@ impl LuaEngine {
     }
 
     fn jail(option: &LuaEngineOption) {
-        let package_path =
-            "!\\?.lua;!\\?\\init.lua;.\\?.lua".replace("!", option.base_dir.to_str().unwrap());
+        let package_path = "!\\?.lua;!\\?\\init.lua;.\\?.lua"
+            .replace("\\", std::path::MAIN_SEPARATOR_STR)
+            .replace("!", option.base_dir.to_str().unwrap());
+        let settings = &option.settings;
         mlua::jail::jail(JailOptions {
-            read_extension_allowlist: Some(["txt", "json"].iter().map(|&s| s.into()).collect()),
+            read_extension_allowlist: Some(
+                settings
+                    .read_extensions
+                    .iter()
+                    .map(|s| s.as_str().into())
+                    .collect(),
+            ),
             write_extension_allowlist: Some(
-                ["txt", "json", "log"].iter().map(|&s| s.into()).collect(),
+                settings
+                    .write_extensions
+                    .iter()
+                    .map(|s| s.as_str().into())
+                    .collect(),
             ),
             os_clock: OsClockOption::DownPrecision(5), // 0.1 sec
             loadfile_root: Some(option.base_dir.clone()),
@@ -252,7 +1060,7 @@ impl LuaEngine {
             // debug_lib: DebugLibOption::deny_all(), // already done by mlua
             getenv: GetEnvOption::Prefix(String::from("OSC_WARDROBE_LUA_")),
             io_root: Some(option.io_dir.clone()),
-            rep_max_len: Some(1024),
+            rep_max_len: Some(settings.rep_max_len),
             ..Default::default()
         });
     }
@@ -278,3 +1086,161 @@ where
         Ok(true)
     }
 }
+
+/// hash of each bundled file at the last extraction, in the lua directory
+const BUNDLED_MANIFEST: &str = ".bundled.json";
+
+/// Brings bundled script changes into an extracted lua directory without losing user edits.
+///
+/// A file still identical to what was extracted last time is overwritten. A file edited by the
+/// user is kept, and the new version is written next to it as `<name>.new`; those are returned.
+pub fn upgrade_lua_dir<P, Q>(src: P, lua_dir: Q) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (src, lua_dir) = (src.as_ref(), lua_dir.as_ref());
+    let manifest_path = lua_dir.join(BUNDLED_MANIFEST);
+    let mut manifest: std::collections::BTreeMap<String, String> =
+        std::fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+    let mut conflicts = vec![];
+    for entry in walkdir::WalkDir::new(src) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(src)?;
+        let key = rel.to_string_lossy().replace('\\', "/");
+        let bundled = std::fs::read(entry.path())?;
+        let bundled_hash = crate::update::sha256_hex(&bundled);
+        let recorded = manifest.get(&key).cloned();
+        if recorded.as_ref() == Some(&bundled_hash) {
+            // not changed since the last extraction; leave user's edits or removal as is
+            continue;
+        }
+        let dst = lua_dir.join(rel);
+        match std::fs::read(&dst) {
+            Err(_) => {
+                debug!("add bundled lua: {}", key);
+                if let Some(dir) = dst.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&dst, &bundled)?;
+            }
+            Ok(current) => {
+                let current_hash = crate::update::sha256_hex(&current);
+                if current_hash != bundled_hash {
+                    if recorded == Some(current_hash) {
+                        debug!("upgrade bundled lua: {}", key);
+                        std::fs::write(&dst, &bundled)?;
+                    } else {
+                        let mut new = dst.clone().into_os_string();
+                        new.push(".new");
+                        warn!("{} is edited, new version is saved as .new", key);
+                        std::fs::write(&new, &bundled)?;
+                        conflicts.push(PathBuf::from(new));
+                    }
+                }
+            }
+        }
+        manifest.insert(key, bundled_hash);
+    }
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(conflicts)
+}
+
+#[test]
+fn upgrade_lua_dir_test() {
+    let root = std::env::temp_dir().join(format!("osc-wardrobe-upgrade-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let (src, dst) = (root.join("src"), root.join("lua"));
+    let write = |path: PathBuf, s: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, s).unwrap();
+    };
+    let read = |path: PathBuf| std::fs::read_to_string(path).ok();
+    write(src.join("main.lua"), "v1");
+    write(src.join("lib/util.lua"), "v1");
+    upgrade_lua_dir(&src, &dst).unwrap();
+    assert_eq!(
+        read(dst.join("lib/util.lua")).as_deref(),
+        Some("v1"),
+        "added"
+    );
+
+    write(dst.join("main.lua"), "edited");
+    write(src.join("main.lua"), "v2");
+    write(src.join("lib/util.lua"), "v2");
+    write(src.join("new.lua"), "v2");
+    let conflicts = upgrade_lua_dir(&src, &dst).unwrap();
+    assert_eq!(
+        read(dst.join("lib/util.lua")).as_deref(),
+        Some("v2"),
+        "upgraded"
+    );
+    assert_eq!(read(dst.join("new.lua")).as_deref(), Some("v2"), "new file");
+    assert_eq!(
+        read(dst.join("main.lua")).as_deref(),
+        Some("edited"),
+        "kept"
+    );
+    assert_eq!(read(dst.join("main.lua.new")).as_deref(), Some("v2"));
+    assert_eq!(conflicts, vec![dst.join("main.lua.new")]);
+
+    std::fs::remove_file(dst.join("new.lua")).unwrap();
+    assert_eq!(
+        upgrade_lua_dir(&src, &dst).unwrap(),
+        Vec::<PathBuf>::new(),
+        "no bundle change"
+    );
+    assert_eq!(read(dst.join("new.lua")), None, "removal is kept");
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn bundle_time_test() {
+    let lua = Lua::new();
+    assert_eq!(bundle_time(&mlua::Value::Nil), Ok(None));
+    let time = lua.create_table().unwrap();
+    time.set("type", "time").unwrap();
+    time.set("seconds", 3_900_000_000u32).unwrap();
+    time.set("fractional", 1u32 << 31).unwrap();
+    assert_eq!(
+        bundle_time(&mlua::Value::Table(time)),
+        Ok(Some(rosc::OscTime {
+            seconds: 3_900_000_000,
+            fractional: 1 << 31
+        }))
+    );
+    let now = rosc::OscTime::try_from(std::time::SystemTime::now()).unwrap();
+    let later = bundle_time(&mlua::Value::Number(1.5)).unwrap().unwrap();
+    assert!(later.seconds > now.seconds, "a delay is relative to now");
+    assert!(bundle_time(&mlua::Value::Integer(0)).unwrap().is_some());
+    assert_eq!(
+        bundle_time(&mlua::Value::Number(-1.0)),
+        Err("invalid delay".to_string())
+    );
+    assert_eq!(
+        bundle_time(&mlua::Value::Number(1e300)),
+        Err("invalid delay".to_string()),
+        "too far to be a time"
+    );
+    assert_eq!(
+        bundle_time(&mlua::Value::Number(1e15)),
+        Err("invalid delay".to_string()),
+        "beyond OSC time"
+    );
+    let color = lua.create_table().unwrap();
+    color.set("type", "color").unwrap();
+    assert_eq!(
+        bundle_time(&mlua::Value::Table(color)),
+        Err("invalid time".to_string())
+    );
+    assert_eq!(
+        bundle_time(&mlua::Value::Boolean(true)),
+        Err("invalid time".to_string())
+    );
+}
@@ -0,0 +1,266 @@
+use crate::application_event::ApplicationEvent;
+use crate::definition::get_definition;
+use crate::json_to_osc;
+use crate::lua::{LuaEngine, LuaEngineEvent, LuaEngineOption};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::sync::mpsc::unbounded_channel;
+
+/// test file
+///
+/// ```json
+/// {
+///   "lua_dir": "../../resources/lua",
+///   "defs_dir": "defs",
+///   "cases": [
+///     {
+///       "name": "switch by parameter",
+///       "input": [["/avatar/change", "avtr_base"], ["/avatar/parameters/Outfit", 1]],
+///       "expect": [["/avatar/change", "avtr_casual"]]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `lua_dir` and `defs_dir` are relative to the test file, and overridden by `--lua` / `--defs`.
+#[derive(serde::Deserialize, Debug)]
+struct TestFile {
+    lua_dir: Option<PathBuf>,
+    defs_dir: Option<PathBuf>,
+    cases: Vec<TestCase>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct TestCase {
+    name: String,
+    #[serde(default)]
+    input: Vec<Vec<serde_json::Value>>,
+    #[serde(default)]
+    expect: Vec<Vec<serde_json::Value>>,
+}
+
+/// `[addr, ...args]`
+fn split_message(message: &[serde_json::Value]) -> Option<(String, Vec<serde_json::Value>)> {
+    let (addr, args) = message.split_first()?;
+    Some((addr.as_str()?.to_string(), args.to_vec()))
+}
+
+/// integers as VRChat sends int parameters, other numbers as float
+fn input_arg(v: &serde_json::Value) -> rosc::OscType {
+    match v.as_i64().map(i32::try_from) {
+        Some(Ok(i)) => rosc::OscType::Int(i),
+        _ => json_to_osc(v),
+    }
+}
+
+/// numbers are compared as float, so `1` in a test file matches both `1` and `1.0` from Lua
+fn as_float(v: &rosc::OscType) -> rosc::OscType {
+    use rosc::OscType::*;
+    match v {
+        Int(i) => Float(*i as f32),
+        Long(l) => Float(*l as f32),
+        Double(d) => Float(*d as f32),
+        Array(a) => Array(rosc::OscArray {
+            content: a.content.iter().map(as_float).collect(),
+        }),
+        _ => v.clone(),
+    }
+}
+
+fn is_same_message(expected: &[serde_json::Value], actual: &(String, Vec<rosc::OscType>)) -> bool {
+    let Some((addr, args)) = split_message(expected) else {
+        return false;
+    };
+    addr == actual.0
+        && args.iter().map(json_to_osc).collect::<Vec<_>>()
+            == actual.1.iter().map(as_float).collect::<Vec<_>>()
+}
+
+fn format_message((addr, args): &(String, Vec<rosc::OscType>)) -> String {
+    format!("{} {:?}", addr, args)
+}
+
+async fn run_case(
+    case: &TestCase,
+    lua_dir: &Path,
+    definition: &serde_json::Value,
+) -> Result<Vec<(String, Vec<rosc::OscType>)>, Box<dyn std::error::Error>> {
+    let (application_event_sender, mut application_event_receiver) = unbounded_channel();
+    let (lua_engine_event_sender, lua_engine_event_receiver) = unbounded_channel();
+    let osc_filter = crate::lua::OscFilter::default();
+    let mut engine = LuaEngine::new(LuaEngineOption {
+        base_dir: lua_dir.to_path_buf(),
+        io_dir: lua_dir.join("io"),
+        lua_engine_event_receiver,
+        application_event_sender: Arc::new(application_event_sender),
+        print_sender: None,
+        settings: Default::default(),
+        osc_filter: osc_filter.clone(),
+        osc_cache: Default::default(),
+    });
+    engine.start().await?;
+    lua_engine_event_sender.send(LuaEngineEvent::DefinitionUpdated(definition.clone()))?;
+    engine.process_event().await;
+
+    for message in &case.input {
+        let Some((addr, args)) = split_message(message) else {
+            return Err(format!("invalid input message: {:?}", message).into());
+        };
+        // as the app does, so that scripts get only what they listen to
+        if !osc_filter.matches(&addr) {
+            continue;
+        }
+        lua_engine_event_sender.send(LuaEngineEvent::OscReceived(
+            addr,
+            args.iter().map(input_arg).collect(),
+        ))?;
+        engine.process_event().await;
+    }
+
+    let mut sent = vec![];
+    while let Ok(event) = application_event_receiver.try_recv() {
+        match event {
+            ApplicationEvent::SendOsc(addr, args) => sent.push((addr, args)),
+            ApplicationEvent::SendOscBundle(messages, _) => sent.extend(messages),
+            _ => {}
+        }
+    }
+    Ok(sent)
+}
+
+fn run_file(
+    path: &Path,
+    lua_dir: Option<&PathBuf>,
+    defs_dir: Option<&PathBuf>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    let test_file: TestFile = serde_json::from_reader(file)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let Some(lua_dir) = lua_dir.cloned().or(test_file.lua_dir.map(|d| base.join(d))) else {
+        return Err("lua dir is not specified".into());
+    };
+    let definition = match defs_dir
+        .cloned()
+        .or(test_file.defs_dir.map(|d| base.join(d)))
+    {
+        Some(defs_dir) => get_definition(&defs_dir),
+        None => serde_json::Value::Null,
+    };
+    std::env::set_current_dir(&lua_dir)?;
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mut failures = 0;
+    for case in &test_file.cases {
+        match rt.block_on(run_case(case, &lua_dir, &definition)) {
+            Ok(sent) => {
+                let passed = sent.len() == case.expect.len()
+                    && case
+                        .expect
+                        .iter()
+                        .zip(sent.iter())
+                        .all(|(expected, actual)| is_same_message(expected, actual));
+                if passed {
+                    println!("ok   {}", case.name);
+                } else {
+                    failures += 1;
+                    println!("FAIL {}", case.name);
+                    for expected in &case.expect {
+                        println!("  expected: {}", serde_json::Value::Array(expected.clone()));
+                    }
+                    for actual in &sent {
+                        println!("  actual:   {}", format_message(actual));
+                    }
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                println!("FAIL {}: {}", case.name, e);
+            }
+        }
+    }
+    Ok(failures)
+}
+
+/// `wardrobe-lua-test [--lua <dir>] [--defs <dir>] <test.json>...`
+pub(crate) fn main() -> ExitCode {
+    let mut lua_dir = None;
+    let mut defs_dir = None;
+    let mut files = vec![];
+    let mut args = std::env::args().skip(1);
+    // resolve paths before `run_file` changes the current directory
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lua" => lua_dir = args.next().and_then(|d| std::path::absolute(d).ok()),
+            "--defs" => defs_dir = args.next().and_then(|d| std::path::absolute(d).ok()),
+            _ => match std::path::absolute(&arg) {
+                Ok(file) => files.push(file),
+                Err(_) => {
+                    eprintln!("invalid path: {:?}", arg);
+                    return ExitCode::from(2);
+                }
+            },
+        }
+    }
+    if files.is_empty() {
+        eprintln!("usage: wardrobe-lua-test [--lua <dir>] [--defs <dir>] <test.json>...");
+        return ExitCode::from(2);
+    }
+
+    let mut failures = 0;
+    for file in &files {
+        println!("# {}", file.display());
+        match run_file(file, lua_dir.as_ref(), defs_dir.as_ref()) {
+            Ok(n) => failures += n,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                failures += 1;
+            }
+        }
+    }
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        println!("{} failure(s)", failures);
+        ExitCode::FAILURE
+    }
+}
+
+#[test]
+fn is_same_message_test() {
+    use rosc::OscType;
+    use serde_json::json;
+    let actual = (
+        "/avatar/change".to_string(),
+        vec![OscType::String("avtr_a".into())],
+    );
+    assert!(is_same_message(
+        &[json!("/avatar/change"), json!("avtr_a")],
+        &actual
+    ));
+    assert!(!is_same_message(
+        &[json!("/avatar/change"), json!("avtr_b")],
+        &actual
+    ));
+    assert!(!is_same_message(&[json!("/avatar/change")], &actual));
+    assert!(
+        is_same_message(
+            &[json!("/a"), json!(1)],
+            &("/a".to_string(), vec![OscType::Float(1.0)])
+        ),
+        "numbers are compared as OSC float"
+    );
+    assert!(
+        is_same_message(
+            &[json!("/a"), json!(1)],
+            &("/a".to_string(), vec![OscType::Int(1)])
+        ),
+        "an int from Lua matches too"
+    );
+    assert!(!is_same_message(
+        &[json!("/a"), json!(2)],
+        &("/a".to_string(), vec![OscType::Int(1)])
+    ));
+}
@@ -1,16 +1,70 @@
 use crate::osc::OscEvent::Message;
-use log::{debug, info, trace, warn};
-use rosc::{OscMessage, OscPacket};
+use crate::osc_query::OscEndpoint;
+use crate::settings::{OscSettings, OscTarget, Settings};
+use log::{debug, error, info, trace, warn};
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::mpsc::{Receiver, UnboundedSender};
-use tokio::sync::Mutex;
-use vrchat_osc::models::{OscNode, OscRootNode};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{unbounded_channel, Receiver, Sender, UnboundedSender};
+use tokio::sync::watch;
+use vrchat_osc::models::OscNode;
 use vrchat_osc::{ServiceType, VRChatOSC};
 
 pub struct OscService {}
 
 pub enum OscEvent {
     Message(OscMessage),
+    Bundle(OscBundle),
+    /// VRChat client is discovered, or reconnected
+    ClientConnected(String, SocketAddr),
+    /// VRChat client is gone
+    ClientDisconnected(String),
+    /// `(target, address, error)` of a packet which could not be sent after retries
+    SendError(String, String, String),
+    Health(OscHealth),
+}
+
+/// timetag `1`: process the bundle on receipt
+pub const IMMEDIATELY: OscTime = OscTime {
+    seconds: 0,
+    fractional: 1,
+};
+
+/// Messages in the packet, nested bundles flattened in order.
+pub fn unpack(packet: OscPacket) -> Vec<OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(unpack).collect(),
+    }
+}
+
+#[test]
+fn unpack_test() {
+    let message = |addr: &str| OscMessage {
+        addr: addr.to_string(),
+        args: vec![],
+    };
+    let packet = OscPacket::Bundle(OscBundle {
+        timetag: IMMEDIATELY,
+        content: vec![
+            OscPacket::Message(message("/a")),
+            OscPacket::Bundle(OscBundle {
+                timetag: IMMEDIATELY,
+                content: vec![OscPacket::Message(message("/b"))],
+            }),
+            OscPacket::Message(message("/c")),
+        ],
+    });
+    assert_eq!(
+        unpack(packet)
+            .into_iter()
+            .map(|m| m.addr)
+            .collect::<Vec<_>>(),
+        vec!["/a", "/b", "/c"]
+    );
 }
 
 fn debug_str_osc_node(node: &OscNode, key: &str, depth: u8, is_last: bool) -> String {
@@ -33,64 +87,561 @@ fn debug_str_osc_node(node: &OscNode, key: &str, depth: u8, is_last: bool) -> St
     lines.join("\n").to_string()
 }
 
+/// Packet to send to a named target of [`OscSettings::targets`].
+#[derive(Debug)]
+pub struct Outgoing {
+    pub target: String,
+    pub packet: OscPacket,
+}
+
+/// Request to [`OscService`].
+#[derive(Debug)]
+pub enum OscRequest {
+    Send(Outgoing),
+    /// to the VRChat client of the name, e.g. a handshake from `on_client_connected`
+    SendToClient(String, OscPacket),
+    /// VRChat client to send to, by name prefix. `None` for `osc.client` in settings
+    SelectClient(Option<String>),
+}
+
+/// target of `osc.send`
+pub const VRCHAT_TARGET: &str = "vrchat";
+
+/// packets kept until VRChat is discovered
+const PENDING_MAX: usize = 64;
+
+/// packets queued for each target while it is sending or retrying
+const QUEUE_MAX: usize = 64;
+
+/// VRChat client found by OSCQuery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Client {
+    pub name: String,
+    pub addr: SocketAddr,
+}
+
+/// Adds `name`, or moves it to the last on reconnection. Other clients at the same address are
+/// stale (VRChat restarted with a new name) and removed; their names are returned.
+fn connect_client(clients: &mut Vec<Client>, name: &str, addr: SocketAddr) -> Vec<String> {
+    let mut removed = vec![];
+    clients.retain(|c| {
+        if c.name == name {
+            false
+        } else if c.addr == addr {
+            removed.push(c.name.clone());
+            false
+        } else {
+            true
+        }
+    });
+    clients.push(Client {
+        name: name.to_string(),
+        addr,
+    });
+    removed
+}
+
+/// Removes `name`, and returns `true` if it was there.
+fn disconnect_client(clients: &mut Vec<Client>, name: &str) -> bool {
+    let len = clients.len();
+    clients.retain(|c| c.name != name);
+    clients.len() != len
+}
+
+/// how often VRChat clients are probed through their OSCQuery server
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// failed probes in a row before a client counts as gone
+const LIVENESS_FAILURES: u32 = 3;
+
+/// Failed probes in a row by client name. VRChat doesn't say goodbye when it quits or crashes.
+#[derive(Default)]
+struct Liveness(HashMap<String, u32>);
+
+impl Liveness {
+    /// Records a probe of `name`, and returns `true` if the client is gone.
+    fn record(&mut self, name: &str, ok: bool) -> bool {
+        if ok {
+            self.0.remove(name);
+            return false;
+        }
+        let failures = self.0.entry(name.to_string()).or_default();
+        *failures += 1;
+        if *failures < LIVENESS_FAILURES {
+            return false;
+        }
+        self.0.remove(name);
+        true
+    }
+}
+
+/// Probes each client every [`LIVENESS_INTERVAL`] and removes the ones which stop answering.
+/// Clients without a known OSCQuery address are left as they are.
+async fn watch_liveness(
+    vrchat_osc: Arc<VRChatOSC>,
+    clients: Arc<watch::Sender<Vec<Client>>>,
+    query_addrs: Arc<std::sync::Mutex<HashMap<String, SocketAddr>>>,
+    events: UnboundedSender<OscEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut liveness = Liveness::default();
+    let mut interval = tokio::time::interval(LIVENESS_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = shutdown.wait_for(|stop| *stop) => break,
+            _ = interval.tick() => {}
+        }
+        let names = clients
+            .borrow()
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        for name in names {
+            let query = query_addrs.lock().expect("query addrs").get(&name).copied();
+            let Some(query) = query else {
+                continue;
+            };
+            let ok = vrchat_osc
+                .get_parameter_from_addr("/avatar/change", query)
+                .await
+                .is_ok();
+            if liveness.record(&name, ok) {
+                info!("OSC server stopped answering: {}", name);
+                clients.send_modify(|clients| {
+                    disconnect_client(clients, &name);
+                });
+                query_addrs.lock().expect("query addrs").remove(&name);
+                let _ = events.send(OscEvent::ClientDisconnected(name));
+            }
+        }
+    }
+}
+
+/// The client whose name starts with `preferred`, or the last connected one.
+fn select_client(clients: &[Client], preferred: Option<&str>) -> Option<SocketAddr> {
+    match preferred {
+        Some(preferred) => clients.iter().rev().find(|c| c.name.starts_with(preferred)),
+        None => clients.last(),
+    }
+    .map(|c| c.addr)
+}
+
+#[test]
+fn clients_test() {
+    let addr = |port: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut clients = vec![];
+    assert!(connect_client(&mut clients, "VRChat-Client-A", addr(9000)).is_empty());
+    assert!(connect_client(&mut clients, "VRChat-Client-B", addr(9002)).is_empty());
+    assert_eq!(select_client(&clients, None), Some(addr(9002)), "last one");
+    assert_eq!(
+        select_client(&clients, Some("VRChat-Client-A")),
+        Some(addr(9000))
+    );
+    assert_eq!(select_client(&clients, Some("VRChat-Client-C")), None);
+
+    assert!(
+        connect_client(&mut clients, "VRChat-Client-A", addr(9004)).is_empty(),
+        "reconnect"
+    );
+    assert_eq!(select_client(&clients, None), Some(addr(9004)));
+    assert_eq!(clients.len(), 2);
+
+    assert_eq!(
+        connect_client(&mut clients, "VRChat-Client-C", addr(9002)),
+        vec!["VRChat-Client-B"],
+        "restarted with new name"
+    );
+    assert_eq!(
+        clients.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+        vec!["VRChat-Client-A", "VRChat-Client-C"]
+    );
+
+    assert!(disconnect_client(&mut clients, "VRChat-Client-C"));
+    assert!(
+        !disconnect_client(&mut clients, "VRChat-Client-C"),
+        "already gone"
+    );
+    assert_eq!(select_client(&clients, None), Some(addr(9004)));
+}
+
+#[test]
+fn liveness_test() {
+    let mut liveness = Liveness::default();
+    assert!(!liveness.record("VRChat-Client-A", false));
+    assert!(!liveness.record("VRChat-Client-A", false));
+    assert!(!liveness.record("VRChat-Client-A", true), "answered again");
+    for _ in 1..LIVENESS_FAILURES {
+        assert!(!liveness.record("VRChat-Client-A", false));
+        assert!(!liveness.record("VRChat-Client-B", true));
+    }
+    assert!(liveness.record("VRChat-Client-A", false), "in a row");
+    assert!(
+        !liveness.record("VRChat-Client-A", false),
+        "counts again after removal"
+    );
+}
+
+/// `[addr, ...args]` of `osc.handshake` in settings
+fn handshake_message(message: &[serde_json::Value]) -> Option<OscMessage> {
+    let (addr, args) = message.split_first()?;
+    Some(OscMessage {
+        addr: addr.as_str()?.to_string(),
+        args: args.iter().map(crate::json_to_osc).collect(),
+    })
+}
+
+/// Connection state shown in the tray.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OscHealth {
+    /// no VRChat client found yet
+    Waiting,
+    Connected,
+    /// the last send failed after retries
+    Failing,
+}
+
+impl std::fmt::Display for OscHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscHealth::Waiting => write!(f, "waiting for VRChat"),
+            OscHealth::Connected => write!(f, "connected"),
+            OscHealth::Failing => write!(f, "send failing"),
+        }
+    }
+}
+
+enum Destination {
+    Vrchat(SocketAddr),
+    Udp(String),
+}
+
+/// OSC address of a message, `#bundle` for bundles
+fn packet_address(packet: &OscPacket) -> String {
+    match packet {
+        OscPacket::Message(message) => message.addr.clone(),
+        OscPacket::Bundle(_) => "#bundle".to_string(),
+    }
+}
+
+async fn send_once(
+    vrchat_osc: &VRChatOSC,
+    socket: &UdpSocket,
+    destination: &Destination,
+    packet: &OscPacket,
+) -> Result<(), String> {
+    match destination {
+        Destination::Vrchat(addr) => vrchat_osc
+            .send_to_addr(packet.clone(), *addr)
+            .await
+            .map_err(|e| format!("{:?}", e)),
+        Destination::Udp(address) => {
+            let buf = rosc::encoder::encode(packet).map_err(|e| format!("{:?}", e))?;
+            socket
+                .send_to(&buf, address.as_str())
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Sends with `osc.retryCount` retries, waiting `osc.retryIntervalMs` * attempt in between.
+async fn send_with_retry(
+    vrchat_osc: &VRChatOSC,
+    socket: &UdpSocket,
+    destination: &Destination,
+    packet: &OscPacket,
+    settings: &OscSettings,
+) -> Result<(), String> {
+    let mut attempt = 0;
+    loop {
+        match send_once(vrchat_osc, socket, destination, packet).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < settings.retry_count => {
+                attempt += 1;
+                debug!("send failed, retry {}: {}", attempt, e);
+                tokio::time::sleep(Duration::from_millis(
+                    settings.retry_interval_ms * attempt as u64,
+                ))
+                .await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A packet for [`send_worker`]. The result of `health` ones is reported for [`OscHealth`].
+struct Job {
+    target: String,
+    destination: Destination,
+    packet: OscPacket,
+    health: bool,
+}
+
+/// Sends packets of one target in order, so that retries to an unreachable target don't hold
+/// up the others. Failures are reported to `events` as [`OscEvent::SendError`].
+async fn send_worker(
+    vrchat_osc: Arc<VRChatOSC>,
+    socket: Arc<UdpSocket>,
+    mut jobs: Receiver<Job>,
+    settings: watch::Receiver<Settings>,
+    events: UnboundedSender<OscEvent>,
+    results: UnboundedSender<bool>,
+) {
+    while let Some(job) = jobs.recv().await {
+        let osc_settings = settings.borrow().osc.clone();
+        let result = send_with_retry(
+            &vrchat_osc,
+            &socket,
+            &job.destination,
+            &job.packet,
+            &osc_settings,
+        )
+        .await;
+        if let Err(e) = &result {
+            warn!("failed to send OSC packet to {}: {}", job.target, e);
+            let _ = events.send(OscEvent::SendError(
+                job.target.clone(),
+                packet_address(&job.packet),
+                e.clone(),
+            ));
+        }
+        if job.health {
+            let _ = results.send(result.is_ok());
+        }
+    }
+}
+
+/// Sends packets from `receiver` to their targets, each through its own [`send_worker`].
+/// VRChat packets sent before the selected client is discovered are queued (up to
+/// [`PENDING_MAX`]) and flushed once it is. Sends to VRChat are reported to `events` as
+/// [`OscEvent::Health`].
+async fn dispatch(
+    vrchat_osc: Arc<VRChatOSC>,
+    mut receiver: Receiver<OscRequest>,
+    mut clients: watch::Receiver<Vec<Client>>,
+    settings: watch::Receiver<Settings>,
+    events: UnboundedSender<OscEvent>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+    let mut pending = VecDeque::new();
+    // from `osc.select_client`, overrides settings
+    let mut selected: Option<String> = None;
+    let mut health = OscHealth::Waiting;
+    let _ = events.send(OscEvent::Health(health));
+    let vrchat_addr = |clients: &[Client], selected: &Option<String>| {
+        let settings = settings.borrow();
+        select_client(
+            clients,
+            selected.as_deref().or(settings.osc.client.as_deref()),
+        )
+    };
+    let (results_sender, mut results) = unbounded_channel();
+    let mut workers = HashMap::<String, Sender<Job>>::new();
+    let mut send = |target: &str, destination: Destination, packet: OscPacket, health: bool| {
+        let worker = workers.entry(target.to_string()).or_insert_with(|| {
+            let (sender, jobs) = tokio::sync::mpsc::channel(QUEUE_MAX);
+            tokio::spawn(send_worker(
+                vrchat_osc.clone(),
+                socket.clone(),
+                jobs,
+                settings.clone(),
+                events.clone(),
+                results_sender.clone(),
+            ));
+            sender
+        });
+        let job = Job {
+            target: target.to_string(),
+            destination,
+            packet,
+            health,
+        };
+        if worker.try_send(job).is_err() {
+            warn!("OSC target {} is not keeping up, drop a packet", target);
+        }
+    };
+    loop {
+        let mut vrchat_result = None;
+        tokio::select! {
+            request = receiver.recv() => {
+                let Some(request) = request else {
+                    warn!("channel is closed");
+                    break;
+                };
+                match request {
+                    OscRequest::SelectClient(name) => {
+                        info!("select VRChat client: {:?}", name);
+                        selected = name;
+                    }
+                    OscRequest::SendToClient(name, packet) => {
+                        let addr = clients
+                            .borrow()
+                            .iter()
+                            .find(|c| c.name == name)
+                            .map(|c| c.addr);
+                        match addr {
+                            Some(addr) => send(&name, Destination::Vrchat(addr), packet, false),
+                            None => warn!("VRChat client not found: {}", name),
+                        }
+                    }
+                    OscRequest::Send(Outgoing { target, packet }) => {
+                        let osc_target = settings.borrow().osc.targets.get(&target).cloned();
+                        trace!("send to {}: {:?}", target, packet);
+                        match osc_target {
+                            Some(OscTarget::Vrchat) => {
+                                let addr = vrchat_addr(&clients.borrow(), &selected);
+                                match addr {
+                                    Some(addr) => {
+                                        send(&target, Destination::Vrchat(addr), packet, true);
+                                    }
+                                    None => {
+                                        if pending.len() >= PENDING_MAX {
+                                            warn!("VRChat is not found yet, drop the oldest packet");
+                                            pending.pop_front();
+                                        }
+                                        pending.push_back(packet);
+                                    }
+                                }
+                            }
+                            Some(OscTarget::Address { address }) => {
+                                send(&target, Destination::Udp(address), packet, false);
+                            }
+                            Some(OscTarget::Loopback { port }) => {
+                                let address = format!("{}:{}", Ipv4Addr::LOCALHOST, port);
+                                send(&target, Destination::Udp(address), packet, false);
+                            }
+                            None => warn!("unknown OSC target: {}", target),
+                        }
+                    }
+                }
+            }
+            Ok(()) = clients.changed() => {
+                clients.borrow_and_update();
+            }
+            Some(ok) = results.recv() => vrchat_result = Some(ok),
+        }
+        let addr = vrchat_addr(&clients.borrow(), &selected);
+        if let Some(addr) = addr {
+            for packet in std::mem::take(&mut pending) {
+                send(VRCHAT_TARGET, Destination::Vrchat(addr), packet, true);
+            }
+        }
+        let new_health = match (addr, vrchat_result) {
+            (None, _) => OscHealth::Waiting,
+            (Some(_), Some(true)) => OscHealth::Connected,
+            (Some(_), Some(false)) => OscHealth::Failing,
+            (Some(_), None) if health == OscHealth::Waiting => OscHealth::Connected,
+            (Some(_), None) => health,
+        };
+        if new_health != health {
+            info!("OSC health: {}", new_health);
+            health = new_health;
+            let _ = events.send(OscEvent::Health(health));
+        }
+    }
+    Ok(())
+}
+
+/// Publishes `/avatar` and `osc.endpoint`s of scripts. Messages to them arrive as [`OscEvent`].
+async fn register(
+    vrchat_osc: &VRChatOSC,
+    service_name: &str,
+    endpoints: &[OscEndpoint],
+    sender: UnboundedSender<OscEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root_node = crate::osc_query::root_node(endpoints);
+    vrchat_osc
+        .register(service_name, root_node, move |packet| {
+            debug!("{:?}", packet);
+            let event = match packet {
+                OscPacket::Message(msg) => Message(msg),
+                OscPacket::Bundle(bundle) => OscEvent::Bundle(bundle),
+            };
+            sender.send(event).unwrap();
+        })
+        .await?;
+    info!(
+        "Service registered: {} ({} endpoints)",
+        service_name,
+        endpoints.len()
+    );
+    Ok(())
+}
+
 impl OscService {
     pub async fn process_osc(
         sender: UnboundedSender<OscEvent>,
-        mut receiver: Receiver<OscEvent>,
+        receiver: Receiver<OscRequest>,
+        settings: watch::Receiver<Settings>,
+        mut endpoints: watch::Receiver<Vec<OscEndpoint>>,
+        mut shutdown: watch::Receiver<bool>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         info!("Initialize VRChatOSC instance");
         let vrchat_osc = VRChatOSC::new().await?;
 
+        let service_name = settings.borrow().osc.service_name.clone();
+        let (clients_sender, clients) = watch::channel(vec![]);
+        let clients_sender = Arc::new(clients_sender);
+        let vrchat_osc_ = vrchat_osc.clone();
+        let events = sender.clone();
+        let dispatch_settings = settings.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                dispatch(vrchat_osc_, receiver, clients, dispatch_settings, events).await
+            {
+                warn!("error on dispatch {:?}", e);
+            }
+        });
+
+        let query_addrs = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        tokio::spawn(watch_liveness(
+            vrchat_osc.clone(),
+            clients_sender.clone(),
+            query_addrs.clone(),
+            sender.clone(),
+            shutdown.clone(),
+        ));
+
         let cloned_vrchat_osc = vrchat_osc.clone();
-        let receiver = Arc::new(Mutex::new(receiver));
+        let connect_sender = sender.clone();
+        let connect_settings = settings.clone();
         vrchat_osc
             .on_connect(move |res| match res {
                 ServiceType::Osc(name, addr) => {
                     info!("Connected to OSC server: {} at {}", name, addr);
                     let vrchat_osc = cloned_vrchat_osc.clone();
-                    // Send a message to the OSC server
-                    let receiver_ = receiver.clone();
+                    let clients_sender = clients_sender.clone();
+                    let sender = connect_sender.clone();
+                    let handshake = connect_settings.borrow().osc.handshake.clone();
                     tokio::spawn(async move {
-                        vrchat_osc
-                            .send_to_addr(
-                                OscPacket::Message(OscMessage {
-                                    addr: "/avatar/parameters/VRChatOSC".to_string(),
-                                    args: vec![rosc::OscType::String("Connected".to_string())],
-                                }),
-                                addr,
-                            )
-                            .await
-                            .unwrap();
-                        info!("Sent message to OSC server.");
-
-                        loop {
-                            tokio::task::yield_now().await;
-                            let mut receiver = receiver_.lock().await;
-                            tokio::select! {
-                                Some(osc_msg) = receiver.recv() => { match osc_msg {
-                                    Message(message) => {
-                                        trace!("send message: {:?} {:?}", message.addr, message.args);
-                                        vrchat_osc
-                                            .send_to_addr(
-                                                OscPacket::Message(message),
-                                                addr,
-                                            )
-                                            .await
-                                            .unwrap();
-                                        info!("Sent message to OSC server.");
-                                    }
-                                };
-                                },
-                                _ = tokio::time::sleep(tokio::time::Duration::from_millis(10)) => {},
-                                else => {
-                                    warn!("channel is closed");
-                                },
+                        if let Some(message) = handshake.as_deref().and_then(handshake_message) {
+                            match vrchat_osc
+                                .send_to_addr(OscPacket::Message(message), addr)
+                                .await
+                            {
+                                Ok(_) => info!("Sent handshake to OSC server."),
+                                Err(e) => warn!("error on handshake {:?}", e),
                             }
                         }
+                        let mut removed = vec![];
+                        clients_sender.send_modify(|clients| {
+                            removed = connect_client(clients, &name, addr);
+                        });
+                        for stale in removed {
+                            info!("Disconnected from stale OSC server: {}", stale);
+                            let _ = sender.send(OscEvent::ClientDisconnected(stale));
+                        }
+                        let _ = sender.send(OscEvent::ClientConnected(name, addr));
                     });
                 }
                 ServiceType::OscQuery(name, addr) => {
                     info!("Connected to OSCQuery server: {} at {}", name, addr);
+                    // the same name as its OSC server, probed by `watch_liveness`
+                    query_addrs
+                        .lock()
+                        .expect("query addrs")
+                        .insert(name.clone(), addr);
                     let vrchat_osc = cloned_vrchat_osc.clone();
                     // Get parameters from the OSCQuery server
                     tokio::spawn(async move {
@@ -111,40 +662,63 @@ impl OscService {
             })
             .await;
 
-        // Register a test service
-        let root_node = OscRootNode::new().with_avatar();
-        let sender_ = sender.clone();
-        vrchat_osc
-            .register("osc_wardrobe", root_node, move |packet| {
-                if let OscPacket::Message(msg) = packet {
-                    debug!("{:?}", msg);
-                    sender_.send(Message(msg)).unwrap();
-                }
-            })
-            .await?;
-        info!("Service registered.");
+        let mut registered = endpoints.borrow_and_update().clone();
+        register(&vrchat_osc, &service_name, &registered, sender.clone()).await?;
 
         // Wait for the service to be registered
         tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
-        // Get parameters from the registered service
-        let params = vrchat_osc
+        // Get parameters from the registered service. Only logged, so a failure must not stop
+        // the endpoints from being kept up to date below
+        match vrchat_osc
             .get_parameter("/avatar/parameters", "VRChat-Client-*")
-            .await?;
-        info!(
-            "Received parameters: \n{}",
-            params
-                .iter()
-                .map(|(name, node)| {
-                    debug_str_osc_node(node, name.to_string().as_ref(), 0, false)
-                })
-                .collect::<Vec<_>>()
-                .join("\n\n")
-        );
+            .await
+        {
+            Ok(params) => info!(
+                "Received parameters: \n{}",
+                params
+                    .iter()
+                    .map(|(name, node)| {
+                        debug_str_osc_node(node, name.to_string().as_ref(), 0, false)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            ),
+            Err(e) => warn!("error on get_parameter {:?}", e),
+        }
 
+        // everything else runs in `vrchat_osc` callbacks
         loop {
-            tokio::task::yield_now().await;
-            tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            tokio::select! {
+                _ = shutdown.wait_for(|stop| *stop) => break,
+                Ok(()) = endpoints.changed() => {
+                    let declared = endpoints.borrow_and_update().clone();
+                    if declared == registered {
+                        continue;
+                    }
+                    if let Err(e) = vrchat_osc.unregister(&service_name).await {
+                        warn!("error on unregister {:?}", e);
+                    }
+                    let result = register(&vrchat_osc, &service_name, &declared, sender.clone())
+                        .await
+                        .map_err(|e| e.to_string());
+                    match result {
+                        Ok(()) => registered = declared,
+                        Err(e) => {
+                            // without the service VRChat can't find the app; give up if the
+                            // previous endpoints can't be restored either
+                            error!("error on register, restore previous endpoints: {}", e);
+                            register(&vrchat_osc, &service_name, &registered, sender.clone())
+                                .await?;
+                        }
+                    }
+                }
+            }
+        }
+        match vrchat_osc.unregister(&service_name).await {
+            Ok(_) => info!("Service unregistered."),
+            Err(e) => warn!("error on unregister {:?}", e),
         }
+        Ok(())
     }
 }
@@ -0,0 +1,106 @@
+//! Latest arguments of each incoming OSC address, for `osc.get(addr)`.
+
+use rosc::OscType;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedValue {
+    pub args: Vec<OscType>,
+    /// last time the address is received
+    pub received_at: SystemTime,
+    /// last time `args` differ from the previous ones
+    pub changed_at: SystemTime,
+}
+
+/// Updated by whoever receives OSC, read by the Lua engine thread.
+#[derive(Clone, Default)]
+pub struct OscCache(Arc<RwLock<HashMap<String, CachedValue>>>);
+
+const AVATAR_PARAMETERS: &str = "/avatar/parameters/";
+
+impl OscCache {
+    /// Records `args` of `addr` and returns `true` if they are new or differ from the last ones.
+    ///
+    /// `/avatar/change` forgets every `/avatar/parameters/*`: VRChat sends them all again for the
+    /// new avatar and scripts start over, so values equal to the previous avatar's are new too.
+    pub fn update(&self, addr: &str, args: &[OscType], now: SystemTime) -> bool {
+        let mut values = self.0.write().expect("osc cache");
+        if addr == crate::AVATAR_CHANGE {
+            values.retain(|addr, _| !addr.starts_with(AVATAR_PARAMETERS));
+        }
+        match values.get_mut(addr) {
+            Some(value) if value.args == args => {
+                value.received_at = now;
+                false
+            }
+            Some(value) => {
+                value.args = args.to_vec();
+                value.received_at = now;
+                value.changed_at = now;
+                true
+            }
+            None => {
+                values.insert(
+                    addr.to_string(),
+                    CachedValue {
+                        args: args.to_vec(),
+                        received_at: now,
+                        changed_at: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    pub fn get(&self, addr: &str) -> Option<CachedValue> {
+        self.0.read().expect("osc cache").get(addr).cloned()
+    }
+}
+
+#[test]
+fn update_test() {
+    let cache = OscCache::default();
+    let t0 = SystemTime::UNIX_EPOCH;
+    let t1 = t0 + std::time::Duration::from_secs(1);
+    let t2 = t0 + std::time::Duration::from_secs(2);
+    let addr = "/avatar/parameters/Outfit";
+    assert_eq!(cache.get(addr), None);
+
+    assert!(cache.update(addr, &[OscType::Int(1)], t0));
+    assert!(!cache.update(addr, &[OscType::Int(1)], t1), "same value");
+    assert_eq!(
+        cache.get(addr),
+        Some(CachedValue {
+            args: vec![OscType::Int(1)],
+            received_at: t1,
+            changed_at: t0,
+        })
+    );
+
+    assert!(cache.update(addr, &[OscType::Int(2)], t2));
+    assert_eq!(cache.get(addr).unwrap().changed_at, t2);
+    assert!(cache.update("/avatar/change", &[], t2), "other address");
+}
+
+#[test]
+fn avatar_change_test() {
+    let cache = OscCache::default();
+    let t0 = SystemTime::UNIX_EPOCH;
+    let outfit = "/avatar/parameters/Outfit";
+    let avatar = |id: &str| [OscType::String(id.to_string())];
+    cache.update("/avatar/change", &avatar("avtr_a"), t0);
+    cache.update(outfit, &[OscType::Int(1)], t0);
+    cache.update("/tracking/vrsystem/head/pose", &[OscType::Float(1.0)], t0);
+
+    cache.update("/avatar/change", &avatar("avtr_b"), t0);
+    assert_eq!(cache.get(outfit), None, "parameters of the previous avatar");
+    assert!(
+        cache.update(outfit, &[OscType::Int(1)], t0),
+        "same value is new for the new avatar"
+    );
+    assert!(cache.get("/tracking/vrsystem/head/pose").is_some());
+    assert!(cache.get("/avatar/change").is_some());
+}
@@ -0,0 +1,241 @@
+//! Conversion between OSC arguments and Lua values, without going through JSON.
+//!
+//! | OSC                | Lua                                                      |
+//! |--------------------|----------------------------------------------------------|
+//! | `Int`, `Long`      | integer                                                  |
+//! | `Float`            | float                                                    |
+//! | `Double`           | `{type = "double", value = 0.1}`                         |
+//! | `String`           | string                                                   |
+//! | `Bool`             | boolean                                                  |
+//! | `Array`            | sequence table                                           |
+//! | `Blob`             | `{type = "blob", data = "<bytes>"}`                      |
+//! | `Time`             | `{type = "time", seconds = 0, fractional = 0}`           |
+//! | `Midi`             | `{type = "midi", port = 0, status = 0, data1 = 0, data2 = 0}` |
+//! | `Color`            | `{type = "color", red = 0, green = 0, blue = 0, alpha = 0}` |
+//! | `Char`             | `{type = "char", value = "c"}`                           |
+//! | `Nil`              | `{type = "nil"}`, also `nil` from Lua                    |
+//! | `Inf`              | `{type = "inf"}`                                         |
+//!
+//! Lua integers go back to `Int` when they fit in 32 bits and `Long` otherwise, floats always to
+//! `Float`, since VRChat ignores `Double` for float parameters. `Double` is tagged both ways, so
+//! it is not narrowed on the way back.
+
+use mlua::{Lua, Table, Value};
+use rosc::OscType;
+
+/// Converts an incoming OSC argument for Lua.
+pub fn to_lua(lua: &Lua, v: &OscType) -> mlua::Result<Value> {
+    let tagged = |tag: &str| -> mlua::Result<Table> {
+        let t = lua.create_table()?;
+        t.set("type", tag)?;
+        Ok(t)
+    };
+    Ok(match v {
+        OscType::Int(i) => Value::Integer(*i as mlua::Integer),
+        OscType::Long(l) => Value::Integer(*l),
+        OscType::Float(f) => Value::Number(*f as f64),
+        OscType::Double(d) => {
+            let t = tagged("double")?;
+            t.set("value", *d)?;
+            Value::Table(t)
+        }
+        OscType::String(s) => Value::String(lua.create_string(s)?),
+        OscType::Bool(b) => Value::Boolean(*b),
+        OscType::Array(a) => Value::Table(to_lua_table(lua, &a.content)?),
+        OscType::Blob(data) => {
+            let t = tagged("blob")?;
+            t.set("data", lua.create_string(data)?)?;
+            Value::Table(t)
+        }
+        OscType::Time(time) => {
+            let t = tagged("time")?;
+            t.set("seconds", time.seconds)?;
+            t.set("fractional", time.fractional)?;
+            Value::Table(t)
+        }
+        OscType::Midi(midi) => {
+            let t = tagged("midi")?;
+            t.set("port", midi.port)?;
+            t.set("status", midi.status)?;
+            t.set("data1", midi.data1)?;
+            t.set("data2", midi.data2)?;
+            Value::Table(t)
+        }
+        OscType::Color(color) => {
+            let t = tagged("color")?;
+            t.set("red", color.red)?;
+            t.set("green", color.green)?;
+            t.set("blue", color.blue)?;
+            t.set("alpha", color.alpha)?;
+            Value::Table(t)
+        }
+        OscType::Char(c) => {
+            let t = tagged("char")?;
+            t.set("value", c.to_string())?;
+            Value::Table(t)
+        }
+        OscType::Nil => Value::Table(tagged("nil")?),
+        OscType::Inf => Value::Table(tagged("inf")?),
+    })
+}
+
+/// Converts OSC arguments to a sequence table, as `args` of `receive(addr, args)`.
+pub fn to_lua_table(lua: &Lua, args: &[OscType]) -> mlua::Result<Table> {
+    let t = lua.create_table_with_capacity(args.len(), 0)?;
+    for (i, arg) in args.iter().enumerate() {
+        t.raw_set(i + 1, to_lua(lua, arg)?)?;
+    }
+    Ok(t)
+}
+
+/// Converts an argument of `osc.send` and friends to the OSC argument sent.
+pub fn from_lua(v: &Value) -> mlua::Result<OscType> {
+    Ok(match v {
+        Value::Nil => OscType::Nil,
+        Value::LightUserData(ud) if ud.0.is_null() => OscType::Nil,
+        Value::Boolean(b) => OscType::Bool(*b),
+        Value::Integer(i) => match i32::try_from(*i) {
+            Ok(i) => OscType::Int(i),
+            Err(_) => OscType::Long(*i),
+        },
+        Value::Number(n) => OscType::Float(*n as f32),
+        Value::String(s) => OscType::String(s.to_str()?.to_string()),
+        Value::Table(t) => match t.get::<Option<String>>("type")? {
+            None => OscType::Array(rosc::OscArray {
+                content: from_lua_table(t)?,
+            }),
+            Some(tag) => from_tagged(&tag, t)?,
+        },
+        _ => {
+            return Err(mlua::Error::FromLuaConversionError {
+                from: v.type_name(),
+                to: "OscType".to_string(),
+                message: None,
+            });
+        }
+    })
+}
+
+/// Converts a sequence table of arguments.
+pub fn from_lua_table(t: &Table) -> mlua::Result<Vec<OscType>> {
+    t.sequence_values::<Value>()
+        .map(|v| from_lua(&v?))
+        .collect()
+}
+
+fn from_tagged(tag: &str, t: &Table) -> mlua::Result<OscType> {
+    Ok(match tag {
+        "blob" => OscType::Blob(t.get::<mlua::String>("data")?.as_bytes().to_vec()),
+        "time" => OscType::Time(rosc::OscTime {
+            seconds: t.get("seconds")?,
+            fractional: t.get("fractional")?,
+        }),
+        "midi" => OscType::Midi(rosc::OscMidiMessage {
+            port: t.get("port")?,
+            status: t.get("status")?,
+            data1: t.get("data1")?,
+            data2: t.get("data2")?,
+        }),
+        "color" => OscType::Color(rosc::OscColor {
+            red: t.get("red")?,
+            green: t.get("green")?,
+            blue: t.get("blue")?,
+            alpha: t.get("alpha")?,
+        }),
+        "char" => {
+            let value = t.get::<String>("value")?;
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => OscType::Char(c),
+                _ => {
+                    return Err(mlua::Error::runtime(format!(
+                        "char must be one character: {:?}",
+                        value
+                    )));
+                }
+            }
+        }
+        "double" => OscType::Double(t.get("value")?),
+        "nil" => OscType::Nil,
+        "inf" => OscType::Inf,
+        _ => return Err(mlua::Error::runtime(format!("unknown OSC type: {}", tag))),
+    })
+}
+
+#[test]
+fn round_trip_test() {
+    let lua = Lua::new();
+    let cases = [
+        OscType::Int(-42),
+        OscType::Int(i32::MAX),
+        OscType::Long(i64::MAX),
+        OscType::Long(i32::MIN as i64 - 1),
+        OscType::Float(0.1),
+        OscType::Float(f32::INFINITY),
+        OscType::Double(0.1),
+        OscType::Double(1e300),
+        OscType::String("avtr_xxx".to_string()),
+        OscType::Bool(true),
+        OscType::Bool(false),
+        OscType::Blob(vec![0, 1, 0xfe, 0xff]),
+        OscType::Time(rosc::OscTime {
+            seconds: 3_900_000_000,
+            fractional: 12345,
+        }),
+        OscType::Midi(rosc::OscMidiMessage {
+            port: 1,
+            status: 0x90,
+            data1: 60,
+            data2: 127,
+        }),
+        OscType::Color(rosc::OscColor {
+            red: 255,
+            green: 128,
+            blue: 0,
+            alpha: 64,
+        }),
+        OscType::Char('衣'),
+        OscType::Nil,
+        OscType::Inf,
+        OscType::Array(rosc::OscArray {
+            content: vec![
+                OscType::Int(1),
+                OscType::Nil,
+                OscType::Array(rosc::OscArray {
+                    content: vec![OscType::String("nested".to_string())],
+                }),
+            ],
+        }),
+    ];
+    for case in cases {
+        let value = to_lua(&lua, &case).expect("to_lua");
+        assert_eq!(from_lua(&value).expect("from_lua"), case);
+    }
+}
+
+#[test]
+fn from_lua_test() {
+    let lua = Lua::new();
+    let eval = |code: &str| from_lua(&lua.load(code).eval::<Value>().unwrap());
+    assert_eq!(eval("1").unwrap(), OscType::Int(1));
+    assert_eq!(eval("1.0").unwrap(), OscType::Float(1.0));
+    assert_eq!(
+        eval("0.1").unwrap(),
+        OscType::Float(0.1),
+        "not exact in f32"
+    );
+    assert_eq!(eval("1e300").unwrap(), OscType::Float(f32::INFINITY));
+    assert_eq!(
+        eval("{type = 'double', value = 0.1}").unwrap(),
+        OscType::Double(0.1)
+    );
+    assert_eq!(eval("nil").unwrap(), OscType::Nil);
+    assert_eq!(
+        eval("{type = 'blob', data = 'ab'}").unwrap(),
+        OscType::Blob(b"ab".to_vec())
+    );
+    assert!(eval("{type = 'midi', port = 256, status = 0, data1 = 0, data2 = 0}").is_err());
+    assert!(eval("{type = 'char', value = 'ab'}").is_err());
+    assert!(eval("{type = 'unknown'}").is_err());
+    assert!(eval("print").is_err());
+}
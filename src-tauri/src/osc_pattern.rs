@@ -0,0 +1,143 @@
+//! OSC 1.0 address pattern matching: `*`, `?`, `[abc]`, `[a-z]`, `[!abc]` and `{foo,bar}`.
+//! Wildcards never match `/`.
+
+/// Checks brackets and braces are closed and not nested.
+pub fn validate(pattern: &str) -> Result<(), String> {
+    if !pattern.starts_with('/') {
+        return Err(format!("pattern must start with '/': {}", pattern));
+    }
+    let mut open = None;
+    for c in pattern.chars() {
+        match (open, c) {
+            (None, '[' | '{') => open = Some(c),
+            (None, ']' | '}') => return Err(format!("unmatched '{}': {}", c, pattern)),
+            (Some('['), ']') | (Some('{'), '}') => open = None,
+            (Some(_), '[' | '{' | '/') => return Err(format!("unclosed bracket: {}", pattern)),
+            _ => {}
+        }
+    }
+    match open {
+        Some(_) => Err(format!("unclosed bracket: {}", pattern)),
+        None => Ok(()),
+    }
+}
+
+/// `true` if `addr` matches `pattern`. Invalid patterns (see [`validate`]) match nothing.
+/// Runs for every incoming message, so it works on the strings as they are without copying.
+pub fn matches(pattern: &str, addr: &str) -> bool {
+    let mut chars = pattern.chars();
+    let Some(p) = chars.next() else {
+        return addr.is_empty();
+    };
+    let rest = chars.as_str();
+    let mut addr_chars = addr.chars();
+    let first = addr_chars.next();
+    let addr_rest = addr_chars.as_str();
+    match p {
+        '*' => {
+            let mut addr = addr;
+            loop {
+                if matches(rest, addr) {
+                    return true;
+                }
+                let mut chars = addr.chars();
+                match chars.next() {
+                    Some(c) if c != '/' => addr = chars.as_str(),
+                    _ => return false,
+                }
+            }
+        }
+        '?' => matches!(first, Some(c) if c != '/') && matches(rest, addr_rest),
+        '[' => {
+            let Some(close) = rest.find(']') else {
+                return false;
+            };
+            match first {
+                Some(c) if c != '/' && in_set(&rest[..close], c) => {
+                    matches(&rest[close + 1..], addr_rest)
+                }
+                _ => false,
+            }
+        }
+        '{' => {
+            let Some(close) = rest.find('}') else {
+                return false;
+            };
+            rest[..close].split(',').any(|alt| {
+                addr.strip_prefix(alt)
+                    .is_some_and(|addr| matches(&rest[close + 1..], addr))
+            })
+        }
+        _ => first == Some(p) && matches(rest, addr_rest),
+    }
+}
+
+/// `abc`, `a-z` or `!abc`
+fn in_set(set: &str, c: char) -> bool {
+    let (negate, set) = match set.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, set),
+    };
+    let mut found = false;
+    let mut chars = set.chars();
+    while let Some(first) = chars.next() {
+        let mut ahead = chars.clone();
+        match (ahead.next(), ahead.next()) {
+            (Some('-'), Some(last)) => {
+                found |= first <= c && c <= last;
+                chars = ahead;
+            }
+            _ => found |= first == c,
+        }
+    }
+    found != negate
+}
+
+#[test]
+fn matches_test() {
+    assert!(matches("/avatar/change", "/avatar/change"));
+    assert!(!matches("/avatar/change", "/avatar/changed"));
+    assert!(matches("/avatar/parameters/*", "/avatar/parameters/Outfit"));
+    assert!(
+        !matches("/avatar/*", "/avatar/parameters/Outfit"),
+        "* does not match /"
+    );
+    assert!(matches(
+        "/avatar/parameters/Outfit?",
+        "/avatar/parameters/Outfit2"
+    ));
+    assert!(!matches(
+        "/avatar/parameters/Outfit?",
+        "/avatar/parameters/Outfit"
+    ));
+    assert!(matches("/a/[0-9]", "/a/5"));
+    assert!(!matches("/a/[0-9]", "/a/x"));
+    assert!(matches("/a/[!0-9]", "/a/x"));
+    assert!(matches("/a/[xyz]", "/a/y"));
+    assert!(matches(
+        "/avatar/parameters/{Outfit,Hat}",
+        "/avatar/parameters/Hat"
+    ));
+    assert!(!matches(
+        "/avatar/parameters/{Outfit,Hat}",
+        "/avatar/parameters/Velocity"
+    ));
+    assert!(matches("/*/parameters/*Hat*", "/avatar/parameters/BigHat2"));
+    assert!(!matches("/a/[0-9", "/a/5"), "invalid pattern");
+    assert!(matches("/a/衣?", "/a/衣装"), "? is one character");
+    assert!(matches("/a/[衣装]*", "/a/装備"));
+    assert!(matches("/a/*/", "/a/b/"));
+    assert!(!matches("/a/*", "/a/b/"));
+    assert!(matches("/a/[a-]", "/a/-"), "- at the end is literal");
+}
+
+#[test]
+fn validate_test() {
+    assert_eq!(validate("/avatar/parameters/{Outfit,Hat}"), Ok(()));
+    assert_eq!(validate("/a/[!0-9]/*"), Ok(()));
+    assert!(validate("avatar").is_err());
+    assert!(validate("/a/[0-9").is_err());
+    assert!(validate("/a/{b,c").is_err());
+    assert!(validate("/a/b}").is_err());
+    assert!(validate("/a/{b,[c]}").is_err());
+}
@@ -0,0 +1,181 @@
+//! Custom OSCQuery nodes declared by scripts with `osc.endpoint`, published next to `/avatar`.
+
+use log::warn;
+use vrchat_osc::models::{OscNode, OscRootNode};
+
+/// `type` of `osc.endpoint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointType {
+    Int,
+    Float,
+    String,
+    Bool,
+}
+
+impl EndpointType {
+    pub fn parse(s: &str) -> Option<EndpointType> {
+        match s {
+            "int" => Some(EndpointType::Int),
+            "float" => Some(EndpointType::Float),
+            "string" => Some(EndpointType::String),
+            "bool" => Some(EndpointType::Bool),
+            _ => None,
+        }
+    }
+
+    /// OSC type tag in the `TYPE` of the node
+    fn tag(self) -> &'static str {
+        match self {
+            EndpointType::Int => "i",
+            EndpointType::Float => "f",
+            EndpointType::String => "s",
+            EndpointType::Bool => "T",
+        }
+    }
+
+    /// `true` if `value` may be the `VALUE` of the node
+    pub fn accepts(self, value: &serde_json::Value) -> bool {
+        match self {
+            EndpointType::Int => value.is_i64(),
+            EndpointType::Float => value.is_number(),
+            EndpointType::String => value.is_string(),
+            EndpointType::Bool => value.is_boolean(),
+        }
+    }
+}
+
+/// `access` of `osc.endpoint`, from the point of view of other OSCQuery clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Access {
+    Read,
+    Write,
+    #[default]
+    ReadWrite,
+}
+
+impl Access {
+    pub fn parse(s: &str) -> Option<Access> {
+        match s {
+            "read" => Some(Access::Read),
+            "write" => Some(Access::Write),
+            "readwrite" => Some(Access::ReadWrite),
+            _ => None,
+        }
+    }
+
+    /// `ACCESS` of the node
+    fn code(self) -> u8 {
+        match self {
+            Access::Read => 1,
+            Access::Write => 2,
+            Access::ReadWrite => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscEndpoint {
+    pub addr: String,
+    pub r#type: EndpointType,
+    pub access: Access,
+    pub description: Option<String>,
+    /// current value shown to clients
+    pub value: Option<serde_json::Value>,
+}
+
+impl OscEndpoint {
+    /// The node in the OSCQuery JSON format.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut node = serde_json::json!({
+            "FULL_PATH": self.addr,
+            "TYPE": self.r#type.tag(),
+            "ACCESS": self.access.code(),
+        });
+        if let Some(description) = &self.description {
+            node["DESCRIPTION"] = description.clone().into();
+        }
+        if let Some(value) = &self.value {
+            node["VALUE"] = serde_json::json!([value]);
+        }
+        node
+    }
+}
+
+/// Endpoints are plain addresses outside of `/avatar`, which belongs to VRChat.
+pub fn validate_address(addr: &str) -> Result<(), String> {
+    if !addr.starts_with('/') || addr.ends_with('/') || addr.contains("//") {
+        return Err(format!("invalid address: {}", addr));
+    }
+    if let Some(c) = addr.chars().find(|c| " #*,?[]{}".contains(*c)) {
+        return Err(format!("'{}' is not allowed in address: {}", c, addr));
+    }
+    if addr == "/avatar" || addr.starts_with("/avatar/") {
+        return Err(format!("/avatar is reserved: {}", addr));
+    }
+    Ok(())
+}
+
+/// `/avatar` and the endpoints. Endpoints the OSCQuery tree rejects are logged and left out.
+pub fn root_node(endpoints: &[OscEndpoint]) -> OscRootNode {
+    let mut root = OscRootNode::new().with_avatar();
+    for endpoint in endpoints {
+        let node = match serde_json::from_value::<OscNode>(endpoint.to_json()) {
+            Ok(node) => node,
+            Err(e) => {
+                warn!("invalid endpoint {}: {:?}", endpoint.addr, e);
+                continue;
+            }
+        };
+        if let Err(e) = root.add_node(node) {
+            warn!("could not add endpoint {}: {:?}", endpoint.addr, e);
+        }
+    }
+    root
+}
+
+#[test]
+fn validate_address_test() {
+    assert_eq!(validate_address("/wardrobe/outfit"), Ok(()));
+    assert!(validate_address("wardrobe").is_err());
+    assert!(validate_address("/wardrobe/").is_err());
+    assert!(validate_address("/wardrobe//outfit").is_err());
+    assert!(validate_address("/wardrobe/*").is_err());
+    assert!(validate_address("/avatar/parameters/Outfit").is_err());
+    assert_eq!(validate_address("/avatars"), Ok(()));
+}
+
+#[test]
+fn to_json_test() {
+    let endpoint = OscEndpoint {
+        addr: "/wardrobe/outfit".to_string(),
+        r#type: EndpointType::String,
+        access: Access::ReadWrite,
+        description: Some("current outfit".to_string()),
+        value: Some("casual".into()),
+    };
+    assert_eq!(
+        endpoint.to_json(),
+        serde_json::json!({
+            "FULL_PATH": "/wardrobe/outfit",
+            "TYPE": "s",
+            "ACCESS": 3,
+            "DESCRIPTION": "current outfit",
+            "VALUE": ["casual"],
+        })
+    );
+    let endpoint = OscEndpoint {
+        addr: "/wardrobe/enabled".to_string(),
+        r#type: EndpointType::Bool,
+        access: Access::Write,
+        description: None,
+        value: None,
+    };
+    assert_eq!(
+        endpoint.to_json(),
+        serde_json::json!({"FULL_PATH": "/wardrobe/enabled", "TYPE": "T", "ACCESS": 2})
+    );
+    assert!(EndpointType::Int.accepts(&1.into()));
+    assert!(!EndpointType::Int.accepts(&1.5.into()));
+    assert!(EndpointType::Float.accepts(&1.into()));
+    assert!(!EndpointType::Bool.accepts(&"true".into()));
+}
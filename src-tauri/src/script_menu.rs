@@ -0,0 +1,153 @@
+//! Tray menu items added by scripts with `wardrobe.menu`, shown above the fixed items.
+//! The Lua engine owns the state; the tray only shows it and reports clicks by id.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptMenuKind {
+    Item,
+    /// checkbox, toggled on click
+    Check(bool),
+    Submenu(Vec<ScriptMenuItem>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptMenuItem {
+    /// unique for the app's lifetime, so a click on a stale menu never reaches a new item
+    pub id: i64,
+    pub text: String,
+    pub enabled: bool,
+    pub kind: ScriptMenuKind,
+}
+
+impl ScriptMenuItem {
+    /// ids of this item and everything under it
+    pub fn ids(&self) -> Vec<i64> {
+        let mut ids = vec![self.id];
+        if let ScriptMenuKind::Submenu(items) = &self.kind {
+            ids.extend(items.iter().flat_map(ScriptMenuItem::ids));
+        }
+        ids
+    }
+}
+
+/// Top-level items, published when `changed`.
+#[derive(Default)]
+pub struct ScriptMenu {
+    pub items: Vec<ScriptMenuItem>,
+    pub changed: bool,
+    last_id: i64,
+}
+
+impl ScriptMenu {
+    pub fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    pub fn add(&mut self, item: ScriptMenuItem) {
+        self.items.push(item);
+        self.changed = true;
+    }
+
+    /// Removes every item, keeping ids increasing.
+    pub fn clear(&mut self) {
+        self.changed = self.changed || !self.items.is_empty();
+        self.items.clear();
+    }
+
+    pub fn find_mut(&mut self, id: i64) -> Option<&mut ScriptMenuItem> {
+        fn find(items: &mut [ScriptMenuItem], id: i64) -> Option<&mut ScriptMenuItem> {
+            for item in items {
+                if item.id == id {
+                    return Some(item);
+                }
+                if let ScriptMenuKind::Submenu(items) = &mut item.kind {
+                    if let Some(item) = find(items, id) {
+                        return Some(item);
+                    }
+                }
+            }
+            None
+        }
+        find(&mut self.items, id)
+    }
+
+    /// Removes the item wherever it is, and returns it with its children.
+    pub fn remove(&mut self, id: i64) -> Option<ScriptMenuItem> {
+        fn remove(items: &mut Vec<ScriptMenuItem>, id: i64) -> Option<ScriptMenuItem> {
+            if let Some(i) = items.iter().position(|item| item.id == id) {
+                return Some(items.remove(i));
+            }
+            items.iter_mut().find_map(|item| match &mut item.kind {
+                ScriptMenuKind::Submenu(items) => remove(items, id),
+                _ => None,
+            })
+        }
+        let removed = remove(&mut self.items, id);
+        self.changed = self.changed || removed.is_some();
+        removed
+    }
+
+    /// Toggles a checkbox. Returns `None` for an unknown or disabled item, otherwise the new
+    /// state of a checkbox or `Some(None)` for a plain item.
+    pub fn click(&mut self, id: i64) -> Option<Option<bool>> {
+        let item = self.find_mut(id).filter(|item| item.enabled)?;
+        match &mut item.kind {
+            ScriptMenuKind::Item => Some(None),
+            ScriptMenuKind::Check(checked) => {
+                *checked = !*checked;
+                let checked = *checked;
+                self.changed = true;
+                Some(Some(checked))
+            }
+            ScriptMenuKind::Submenu(_) => None,
+        }
+    }
+}
+
+#[test]
+fn script_menu_test() {
+    let mut menu = ScriptMenu::default();
+    let item = |id, kind| ScriptMenuItem {
+        id,
+        text: format!("item {}", id),
+        enabled: true,
+        kind,
+    };
+    let (auto, outfit, casual, formal) = (
+        menu.next_id(),
+        menu.next_id(),
+        menu.next_id(),
+        menu.next_id(),
+    );
+    menu.add(item(auto, ScriptMenuKind::Check(true)));
+    menu.add(item(
+        outfit,
+        ScriptMenuKind::Submenu(vec![
+            item(casual, ScriptMenuKind::Item),
+            item(formal, ScriptMenuKind::Check(false)),
+        ]),
+    ));
+    assert!(menu.changed);
+    menu.changed = false;
+
+    assert_eq!(menu.click(auto), Some(Some(false)));
+    assert!(menu.changed, "checkbox state is published");
+    menu.changed = false;
+    assert_eq!(menu.click(casual), Some(None));
+    assert!(!menu.changed);
+    assert_eq!(menu.click(formal), Some(Some(true)), "nested");
+    assert_eq!(menu.click(outfit), None, "submenu itself");
+    assert_eq!(menu.click(99), None);
+
+    menu.find_mut(casual).unwrap().enabled = false;
+    assert_eq!(menu.click(casual), None, "disabled");
+
+    assert_eq!(
+        menu.remove(outfit).map(|item| item.ids()),
+        Some(vec![outfit, casual, formal])
+    );
+    assert_eq!(menu.remove(formal), None, "removed with its submenu");
+    menu.clear();
+    assert!(menu.items.is_empty());
+    assert!(menu.next_id() > formal, "ids are not reused");
+}
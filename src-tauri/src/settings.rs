@@ -0,0 +1,485 @@
+use crate::AppState;
+use log::{info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// `settings.json` in AppData. Missing keys take their default value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// wait after the last change in the definitions directory before reloading it
+    pub definitions_debounce_ms: u64,
+    /// max log level: `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: String,
+    pub update: UpdateSettings,
+    pub lua: LuaSettings,
+    pub osc: OscSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UpdateSettings {
+    pub channel: UpdateChannel,
+    /// GitHub `owner/name` to look for releases
+    pub repository: String,
+    /// releases API URL, e.g. a fork or a mirror. `repository` on api.github.com if unset
+    pub endpoint: Option<String>,
+    /// no request to `endpoint` within this time from the last check
+    pub min_check_interval_minutes: u64,
+    /// download and checksum the installer when notified, to install on next start
+    pub auto_install: bool,
+    /// version the user chose to skip
+    pub skipped_version: Option<String>,
+    /// RFC 3339 time; no notification until then
+    pub remind_after: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateChannel {
+    /// `prerelease` if the running version is a pre-release, `stable` otherwise
+    #[default]
+    Auto,
+    /// releases without pre-release versions
+    Stable,
+    /// all releases
+    Prerelease,
+    /// no update check
+    Off,
+}
+
+/// Sandbox of Lua scripts. Applied by reloading Lua.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LuaSettings {
+    /// max length of `string.rep` result
+    pub rep_max_len: usize,
+    /// file extensions `io` can read
+    pub read_extensions: Vec<String>,
+    /// file extensions `io` can write
+    pub write_extensions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OscSettings {
+    /// output targets by name, for `osc.send_to(name, ...)`. `osc.send` goes to `vrchat`, which
+    /// must be present
+    pub targets: BTreeMap<String, OscTarget>,
+    /// name prefix of the VRChat client to send to, e.g. `VRChat-Client-A1B2C3`.
+    /// the last connected one if unset
+    pub client: Option<String>,
+    /// `[addr, ...args]` sent to each VRChat client on connect, off if unset
+    pub handshake: Option<Vec<serde_json::Value>>,
+    /// OSCQuery service name; should differ between instances on one machine
+    pub service_name: String,
+    /// retries after a failed send
+    pub retry_count: u32,
+    /// wait before the n-th retry is n times this
+    pub retry_interval_ms: u64,
+    /// pass a received address to scripts only when its arguments differ from the last ones.
+    /// `osc.get(addr)` has the latest either way
+    pub changed_only: bool,
+    /// least time between `/avatar/change`s sent to VRChat; requests in between are coalesced
+    /// to the last one
+    pub avatar_change_cooldown_ms: u64,
+    /// how long to wait for VRChat to confirm a sent `/avatar/change`
+    pub avatar_change_timeout_ms: u64,
+    /// VRChat's `OSC` folder of per-avatar configs.
+    /// [`crate::avatar_config::default_dir`] if unset
+    pub vrchat_osc_dir: Option<String>,
+}
+
+/// Where to send OSC packets.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum OscTarget {
+    /// VRChat client found by OSCQuery
+    Vrchat,
+    /// fixed `host:port`
+    Address { address: String },
+    /// `127.0.0.1:port`
+    Loopback { port: u16 },
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            definitions_debounce_ms: 2000,
+            log_level: "trace".to_string(),
+            update: UpdateSettings::default(),
+            lua: LuaSettings::default(),
+            osc: OscSettings::default(),
+        }
+    }
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        UpdateSettings {
+            channel: UpdateChannel::default(),
+            repository: "called-d/osc-wardrobe".to_string(),
+            endpoint: None,
+            min_check_interval_minutes: 60,
+            auto_install: false,
+            skipped_version: None,
+            remind_after: None,
+        }
+    }
+}
+
+impl Default for LuaSettings {
+    fn default() -> Self {
+        LuaSettings {
+            rep_max_len: 1024,
+            read_extensions: vec!["txt".to_string(), "json".to_string()],
+            write_extensions: vec!["txt".to_string(), "json".to_string(), "log".to_string()],
+        }
+    }
+}
+
+impl Default for OscSettings {
+    fn default() -> Self {
+        OscSettings {
+            targets: BTreeMap::from([("vrchat".to_string(), OscTarget::Vrchat)]),
+            client: None,
+            handshake: None,
+            service_name: "osc_wardrobe".to_string(),
+            retry_count: 3,
+            retry_interval_ms: 200,
+            changed_only: false,
+            avatar_change_cooldown_ms: 5_000,
+            avatar_change_timeout_ms: 30_000,
+            vrchat_osc_dir: None,
+        }
+    }
+}
+
+fn is_valid_extension(ext: &str) -> bool {
+    !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl UpdateSettings {
+    /// URL returning GitHub style releases array
+    pub fn releases_url(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("https://api.github.com/repos/{}/releases", self.repository),
+        }
+    }
+}
+
+impl OscSettings {
+    pub fn vrchat_osc_dir(&self) -> Option<std::path::PathBuf> {
+        match &self.vrchat_osc_dir {
+            Some(dir) => Some(dir.into()),
+            None => crate::avatar_config::default_dir(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn level_filter(&self) -> LevelFilter {
+        LevelFilter::from_str(&self.log_level).unwrap_or(LevelFilter::Trace)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(100..=60_000).contains(&self.definitions_debounce_ms) {
+            return Err("definitionsDebounceMs must be between 100 and 60000".to_string());
+        }
+        if LevelFilter::from_str(&self.log_level).is_err() {
+            return Err(format!("unknown log level: {}", self.log_level));
+        }
+        let parts = self.update.repository.split('/').collect::<Vec<_>>();
+        if parts.len() != 2 || parts.iter().any(|p| p.is_empty()) {
+            return Err(format!(
+                "repository must be owner/name: {}",
+                self.update.repository
+            ));
+        }
+        if self.update.min_check_interval_minutes > 43_200 {
+            return Err("minCheckIntervalMinutes must be 43200 (30 days) or less".to_string());
+        }
+        if let Some(endpoint) = &self.update.endpoint {
+            if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                return Err(format!("endpoint must be http(s) URL: {}", endpoint));
+            }
+        }
+        if self.lua.rep_max_len == 0 {
+            return Err("repMaxLen must be greater than 0".to_string());
+        }
+        for ext in self
+            .lua
+            .read_extensions
+            .iter()
+            .chain(self.lua.write_extensions.iter())
+        {
+            if !is_valid_extension(ext) {
+                return Err(format!("invalid extension: {:?}", ext));
+            }
+        }
+        if let Some(handshake) = &self.osc.handshake {
+            if !handshake
+                .first()
+                .and_then(|a| a.as_str())
+                .is_some_and(|a| a.starts_with('/'))
+            {
+                return Err("handshake must be [\"/address\", ...args]".to_string());
+            }
+            if handshake[0] == crate::AVATAR_CHANGE {
+                return Err("handshake can't be /avatar/change".to_string());
+            }
+        }
+        let name = &self.osc.service_name;
+        if name.is_empty()
+            || name.len() > 63
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("invalid service name: {:?}", name));
+        }
+        if self.osc.retry_count > 10 {
+            return Err("retryCount must be 10 or less".to_string());
+        }
+        if self.osc.retry_interval_ms > 5_000 {
+            return Err("retryIntervalMs must be 5000 or less".to_string());
+        }
+        if self.osc.avatar_change_cooldown_ms > 600_000 {
+            return Err("avatarChangeCooldownMs must be 600000 or less".to_string());
+        }
+        if !(1_000..=600_000).contains(&self.osc.avatar_change_timeout_ms) {
+            return Err("avatarChangeTimeoutMs must be between 1000 and 600000".to_string());
+        }
+        if !self.osc.targets.contains_key(crate::osc::VRCHAT_TARGET) {
+            return Err(format!(
+                "targets must have {:?} for osc.send",
+                crate::osc::VRCHAT_TARGET
+            ));
+        }
+        for (name, target) in &self.osc.targets {
+            if let OscTarget::Address { address } = target {
+                let valid = address
+                    .rsplit_once(':')
+                    .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+                if !valid {
+                    return Err(format!("invalid address of target {}: {}", name, address));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads settings, falling back to default if the file is missing or broken.
+pub fn load(path: &Path) -> Settings {
+    let Ok(file) = std::fs::File::open(path) else {
+        info!("no settings file, use default: {:?}", path);
+        return Settings::default();
+    };
+    match serde_json::from_reader::<_, Settings>(file) {
+        Ok(settings) => match settings.validate() {
+            Ok(()) => settings,
+            Err(e) => {
+                warn!("invalid settings, use default: {}", e);
+                Settings::default()
+            }
+        },
+        Err(e) => {
+            warn!("could not parse settings, use default: {:?}", e);
+            Settings::default()
+        }
+    }
+}
+
+pub fn save(path: &Path, settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(settings)?)?;
+    Ok(())
+}
+
+/// Changes settings from the app side, e.g. tray menu actions.
+pub(crate) fn modify_settings(
+    app: &AppHandle,
+    f: impl FnOnce(&mut Settings),
+) -> Result<(), String> {
+    let state = app.state::<Mutex<AppState>>();
+    let mut settings = state
+        .lock()
+        .expect("get state (modify_settings)")
+        .settings
+        .borrow()
+        .clone();
+    f(&mut settings);
+    set_settings(app.clone(), state, settings)
+}
+
+#[tauri::command]
+pub(crate) fn get_settings(state: tauri::State<Mutex<AppState>>) -> Settings {
+    state
+        .lock()
+        .expect("get state (get_settings)")
+        .settings
+        .borrow()
+        .clone()
+}
+
+/// Validates, saves and notifies subsystems and the frontend (`settings-changed` event).
+#[tauri::command]
+pub(crate) fn set_settings(
+    app: AppHandle,
+    state: tauri::State<Mutex<AppState>>,
+    settings: Settings,
+) -> Result<(), String> {
+    settings.validate()?;
+    save(&crate::settings_path(&app), &settings).map_err(|e| e.to_string())?;
+    {
+        let state = state.lock().expect("get state (set_settings)");
+        state.settings.send_replace(settings.clone());
+    }
+    app.emit("settings-changed", &settings)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// `true` if `f` makes the default settings invalid
+#[cfg(test)]
+fn invalid(f: fn(&mut Settings)) -> bool {
+    let mut settings = Settings::default();
+    f(&mut settings);
+    settings.validate().is_err()
+}
+
+#[test]
+fn settings_test() {
+    assert_eq!(Settings::default().validate(), Ok(()));
+    assert_eq!(
+        serde_json::from_str::<Settings>(r#"{"definitionsDebounceMs": 500}"#).unwrap(),
+        Settings {
+            definitions_debounce_ms: 500,
+            ..Default::default()
+        },
+        "missing keys are default"
+    );
+    assert!(
+        invalid(|s| s.definitions_debounce_ms = 0),
+        "too short debounce"
+    );
+}
+
+#[test]
+fn logging_settings_test() {
+    assert_eq!(Settings::default().level_filter(), LevelFilter::Trace);
+    let warn = Settings {
+        log_level: "warn".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(warn.level_filter(), LevelFilter::Warn);
+    assert!(
+        invalid(|s| s.log_level = "verbose".to_string()),
+        "unknown level"
+    );
+}
+
+#[test]
+fn update_settings_test() {
+    assert!(
+        invalid(|s| s.update.repository = "osc-wardrobe".to_string()),
+        "no owner"
+    );
+    assert!(
+        invalid(|s| s.update.endpoint = Some("file:///etc/passwd".to_string())),
+        "non http endpoint"
+    );
+    assert!(
+        invalid(|s| s.update.min_check_interval_minutes = u64::MAX),
+        "interval overflowing chrono::Duration"
+    );
+    assert!(
+        invalid(|s| s.update.min_check_interval_minutes = 43_201),
+        "longer than a month"
+    );
+    assert!(!invalid(|s| s.update.min_check_interval_minutes = 43_200));
+    assert_eq!(
+        serde_json::from_str::<UpdateSettings>(r#"{"channel": "off"}"#)
+            .unwrap()
+            .channel,
+        UpdateChannel::Off
+    );
+    assert_eq!(
+        UpdateSettings::default().releases_url(),
+        "https://api.github.com/repos/called-d/osc-wardrobe/releases"
+    );
+}
+
+#[test]
+fn lua_settings_test() {
+    assert!(invalid(|s| s.lua.rep_max_len = 0), "zero rep_max_len");
+    assert!(
+        invalid(|s| s.lua.write_extensions.push("../exe".to_string())),
+        "extension with path"
+    );
+}
+
+#[test]
+fn osc_settings_test() {
+    assert!(
+        invalid(|s| {
+            s.osc.targets.insert(
+                "vmc".to_string(),
+                OscTarget::Address {
+                    address: "localhost".to_string(),
+                },
+            );
+        }),
+        "no port"
+    );
+    assert!(
+        invalid(|s| {
+            s.osc.targets.remove("vrchat");
+        }),
+        "no vrchat target"
+    );
+    assert!(
+        serde_json::from_str::<Settings>(
+            r#"{"osc": {"targets": {"vmc": {"type": "loopback", "port": 39539}}}}"#
+        )
+        .unwrap()
+        .validate()
+        .is_err(),
+        "targets from the user replace the default ones"
+    );
+    assert!(
+        invalid(|s| s.osc.handshake = Some(vec![serde_json::json!("Connected")])),
+        "handshake without address"
+    );
+    assert!(
+        invalid(|s| s.osc.handshake = Some(vec![
+            serde_json::json!("/avatar/change"),
+            serde_json::json!("avtr_xxx")
+        ])),
+        "handshake bypassing the avatar change guard"
+    );
+    assert!(
+        invalid(|s| s.osc.service_name = "wardrobe 2".to_string()),
+        "space in service name"
+    );
+    assert!(invalid(|s| s.osc.avatar_change_cooldown_ms = 3_600_000));
+    assert!(invalid(|s| s.osc.avatar_change_timeout_ms = 0));
+    assert_eq!(
+        serde_json::from_str::<OscSettings>(
+            r#"{"targets": {"vmc": {"type": "address", "address": "127.0.0.1:39539"}}}"#
+        )
+        .unwrap()
+        .targets["vmc"],
+        OscTarget::Address {
+            address: "127.0.0.1:39539".to_string()
+        }
+    );
+}
@@ -0,0 +1,111 @@
+use crate::lua::LuaEngineEvent;
+use log::{debug, info, warn};
+use std::future::Future;
+use tauri::async_runtime::JoinHandle;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, watch};
+use tokio::time::Duration;
+
+/// How long each background task may take to stop.
+const TASK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stops background tasks in order before the process exits.
+///
+/// 1. Lua: [`LuaEngineEvent::Stop`] lets scripts run `stop()`. [`Shutdown::stop_lua`] runs on
+///    its own so the event processor keeps sending what scripts send meanwhile
+/// 2. services watching [`Shutdown::signal`] (OSC, definitions watcher)
+/// 3. log forwarding watching [`Shutdown::log_signal`], last so the shutdown itself is logged
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+    log_sender: watch::Sender<bool>,
+    lua: Option<(UnboundedSender<LuaEngineEvent>, oneshot::Receiver<()>)>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+    log_task: Option<JoinHandle<()>>,
+    started: bool,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown {
+            sender: watch::channel(false).0,
+            log_sender: watch::channel(false).0,
+            lua: None,
+            tasks: vec![],
+            log_task: None,
+            started: false,
+        }
+    }
+}
+
+impl Shutdown {
+    /// becomes `true` when services should stop
+    pub fn signal(&self) -> watch::Receiver<bool> {
+        self.sender.subscribe()
+    }
+
+    /// becomes `true` when log forwarding should flush and stop
+    pub fn log_signal(&self) -> watch::Receiver<bool> {
+        self.log_sender.subscribe()
+    }
+
+    /// `stopped` is sent when [`crate::lua::LuaEngine::run`] returns
+    pub fn set_lua(
+        &mut self,
+        sender: UnboundedSender<LuaEngineEvent>,
+        stopped: oneshot::Receiver<()>,
+    ) {
+        self.lua = Some((sender, stopped));
+    }
+
+    pub fn add_task(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.tasks.push((name, handle));
+    }
+
+    pub fn set_log_task(&mut self, handle: JoinHandle<()>) {
+        self.log_task = Some(handle);
+    }
+
+    /// Step 1 alone, done by [`Shutdown::run`] if not taken before.
+    pub fn stop_lua(&mut self) -> impl Future<Output = ()> + Send + 'static {
+        if !self.started {
+            info!("shutdown start");
+            self.started = true;
+        }
+        let lua = self.lua.take();
+        async move {
+            let Some((sender, stopped)) = lua else {
+                return;
+            };
+            if sender.send(LuaEngineEvent::Stop).is_ok() {
+                match tokio::time::timeout(TASK_TIMEOUT, stopped).await {
+                    Ok(_) => debug!("lua stopped"),
+                    Err(_) => warn!("lua did not stop in time"),
+                }
+            }
+        }
+    }
+
+    pub async fn run(mut self) {
+        self.stop_lua().await;
+
+        let _ = self.sender.send(true);
+        for (name, handle) in self.tasks {
+            wait(name, handle).await;
+        }
+        info!("shutdown done");
+
+        log::logger().flush();
+        let _ = self.log_sender.send(true);
+        if let Some(handle) = self.log_task {
+            wait("log", handle).await;
+        }
+    }
+}
+
+async fn wait(name: &str, handle: JoinHandle<()>) {
+    match tokio::time::timeout(TASK_TIMEOUT, handle).await {
+        Ok(Ok(())) => debug!("{} stopped", name),
+        Ok(Err(e)) => warn!("{} failed: {:?}", name, e),
+        Err(_) => warn!("{} did not stop in time", name),
+    }
+}
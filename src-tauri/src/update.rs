@@ -1,20 +1,163 @@
-use log::info;
+use crate::settings::{UpdateChannel, UpdateSettings};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
 use nyquest::r#async::Request;
 use semver::Version;
+use std::path::Path;
 
-#[derive(serde::Deserialize, PartialEq, Clone, Debug)]
+#[derive(serde::Deserialize, PartialEq, Clone, Debug, Default)]
 struct Release {
     html_url: String,
     tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    published_at: Option<String>,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub content_type: String,
+}
+
+/// Newer release, sent to the frontend as `update-available` event.
+#[derive(serde::Serialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInfo {
+    pub version: String,
+    /// release page
+    pub url: String,
+    /// release notes (markdown)
+    pub notes: String,
+    /// RFC 3339
+    pub published_at: Option<String>,
+    pub prerelease: bool,
+    pub assets: Vec<Asset>,
+    /// direct link of the installer for this platform
+    pub installer_url: Option<String>,
+}
+
+impl From<Release> for UpdateInfo {
+    fn from(release: Release) -> Self {
+        let installer_url =
+            installer_asset(&release.assets).map(|a| a.browser_download_url.clone());
+        UpdateInfo {
+            version: parse_tag(&release.tag_name)
+                .map(|v| v.to_string())
+                .unwrap_or(release.tag_name),
+            url: release.html_url,
+            notes: release.body.unwrap_or_default(),
+            published_at: release.published_at,
+            prerelease: release.prerelease,
+            assets: release.assets,
+            installer_url,
+        }
+    }
+}
+
+/// `0.2.0` or `v0.2.0`
+fn parse_tag(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// installer suffixes of Tauri bundles, preferred first
+fn installer_suffixes() -> &'static [&'static str] {
+    if cfg!(target_os = "windows") {
+        &["-setup.exe", ".msi"]
+    } else if cfg!(target_os = "macos") {
+        &[".dmg"]
+    } else {
+        &[".AppImage", ".deb", ".rpm"]
+    }
+}
+
+fn installer_asset(assets: &[Asset]) -> Option<&Asset> {
+    installer_suffixes()
+        .iter()
+        .find_map(|suffix| assets.iter().find(|a| a.name.ends_with(suffix)))
+}
+
+/// false if the user skipped this version or asked to be reminded later
+pub fn should_notify(
+    settings: &UpdateSettings,
+    info: &UpdateInfo,
+    now: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    if settings.skipped_version.as_deref() == Some(info.version.as_str()) {
+        return false;
+    }
+    if let Some(remind_after) = &settings.remind_after {
+        if let Ok(remind_after) = chrono::DateTime::parse_from_rfc3339(remind_after) {
+            return now >= remind_after;
+        }
+    }
+    true
+}
+
+#[test]
+fn should_notify_test() {
+    let info = UpdateInfo {
+        version: "0.2.0".to_string(),
+        ..Default::default()
+    };
+    let now = chrono::DateTime::parse_from_rfc3339("2025-01-02T00:00:00Z")
+        .unwrap()
+        .to_utc();
+    let settings = |skipped_version: Option<&str>, remind_after: Option<&str>| UpdateSettings {
+        skipped_version: skipped_version.map(|s| s.to_string()),
+        remind_after: remind_after.map(|s| s.to_string()),
+        ..Default::default()
+    };
+    assert!(should_notify(&settings(None, None), &info, now), "default");
+    assert!(
+        !should_notify(&settings(Some("0.2.0"), None), &info, now),
+        "skipped"
+    );
+    assert!(
+        should_notify(&settings(Some("0.1.1"), None), &info, now),
+        "skipped older version"
+    );
+    assert!(
+        !should_notify(&settings(None, Some("2025-01-03T00:00:00Z")), &info, now),
+        "remind later"
+    );
+    assert!(
+        should_notify(&settings(None, Some("2025-01-01T00:00:00Z")), &info, now),
+        "remind time passed"
+    );
 }
 
 /// "new" version from releases array
-fn get_target_release(current: Version, releases: Vec<Release>) -> Option<Release> {
-    let omit_prereleases = current.pre.is_empty();
+fn get_target_release(
+    current: Version,
+    releases: Vec<Release>,
+    channel: UpdateChannel,
+) -> Option<Release> {
+    let omit_prereleases = match channel {
+        UpdateChannel::Auto => current.pre.is_empty(),
+        UpdateChannel::Stable => true,
+        UpdateChannel::Prerelease => false,
+        UpdateChannel::Off => return None,
+    };
     releases
         .iter()
         .filter_map(|rel| {
-            let Ok(v) = Version::parse(&rel.tag_name) else {
+            if rel.draft {
+                return None;
+            }
+            let Some(v) = parse_tag(&rel.tag_name) else {
                 return None;
             };
             if omit_prereleases && !v.pre.is_empty() {
@@ -36,6 +179,15 @@ fn get_target_release_test() {
             Release {
                 html_url: $x.to_string(),
                 tag_name: $x.to_string(),
+                ..Default::default()
+            }
+        };
+    }
+    macro_rules! draft {
+        ( $x:literal ) => {
+            Release {
+                draft: true,
+                ..release!($x)
             }
         };
     }
@@ -44,67 +196,648 @@ fn get_target_release_test() {
             vec![ $( release!($x), )* ]
         };
     }
+    use UpdateChannel::*;
     macro_rules! vp {
         ( $x:literal ) => {
             Version::parse($x).unwrap()
         };
     }
     assert_eq!(
-        get_target_release(vp!("0.0.0"), vervec![]),
+        get_target_release(vp!("0.0.0"), vervec![], Stable),
         None,
         "empty array -> None"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.0"), vervec!["0.0.0", "0.1.0"]),
+        get_target_release(vp!("0.1.0"), vervec!["0.0.0", "0.1.0"], Stable),
         None,
         "current is latest release"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.1"), vervec!["0.0.0", "0.1.0"]),
+        get_target_release(vp!("0.1.1"), vervec!["0.0.0", "0.1.0"], Stable),
         None,
         "current is in future version"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.0"), vervec!["0.1.1"]),
+        get_target_release(vp!("0.1.0"), vervec!["0.1.1"], Stable),
         Some(release!("0.1.1")),
         "has new latest release"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.0-rc.1"), vervec!["0.1.0-alpha.1", "0.1.0"]),
+        get_target_release(vp!("0.1.0-rc.1"), vervec!["0.1.0-alpha.1", "0.1.0"], Auto),
         Some(release!("0.1.0")),
         "see other channel"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.0"), vervec!["0.1.0", "0.2.0-rc.1"]),
+        get_target_release(vp!("0.1.0"), vervec!["0.1.0", "0.2.0-rc.1"], Stable),
         None,
         "ignore prerelease"
     );
     assert_eq!(
-        get_target_release(vp!("0.1.0"), vervec!["0.1.0", "0.2.0-rc.1", "0.2.0"]),
+        get_target_release(
+            vp!("0.1.0"),
+            vervec!["0.1.0", "0.2.0-rc.1", "0.2.0"],
+            Stable
+        ),
         Some(release!("0.2.0")),
         "skip prerelease but see latest"
     );
+    assert_eq!(
+        get_target_release(
+            vp!("0.1.0"),
+            vec![release!("0.1.1"), draft!("0.2.0")],
+            Stable
+        ),
+        Some(release!("0.1.1")),
+        "ignore draft"
+    );
+    assert_eq!(
+        get_target_release(vp!("0.1.0"), vec![draft!("0.2.0")], Stable),
+        None,
+        "only draft"
+    );
+    assert_eq!(
+        get_target_release(vp!("0.1.0"), vervec!["0.1.1", "v0.2.0"], Stable),
+        Some(release!("v0.2.0")),
+        "v prefixed tag"
+    );
+    assert_eq!(
+        get_target_release(
+            vp!("0.1.0"),
+            vervec!["0.1.1", "release-0.3", "0.4", ""],
+            Stable
+        ),
+        Some(release!("0.1.1")),
+        "ignore malformed tags"
+    );
+    assert_eq!(
+        get_target_release(vp!("0.1.0"), vervec!["0.1.1", "0.2.0-rc.1"], Prerelease),
+        Some(release!("0.2.0-rc.1")),
+        "prerelease channel"
+    );
+    assert_eq!(
+        get_target_release(vp!("0.1.0-rc.1"), vervec!["0.1.0-rc.2"], Stable),
+        None,
+        "stable channel ignores prerelease even if current is prerelease"
+    );
+    assert_eq!(
+        get_target_release(
+            vp!("0.1.0-rc.1"),
+            vervec!["0.1.0-rc.2"],
+            UpdateChannel::default()
+        ),
+        Some(release!("0.1.0-rc.2")),
+        "prerelease user keeps getting prereleases by default"
+    );
+    assert_eq!(
+        get_target_release(
+            vp!("0.1.0"),
+            vervec!["0.2.0-rc.1"],
+            UpdateChannel::default()
+        ),
+        None,
+        "stable user gets stable releases by default"
+    );
+    assert_eq!(
+        get_target_release(vp!("0.1.0"), vervec!["0.2.0"], Off),
+        None,
+        "off"
+    );
+}
+
+#[test]
+fn update_info_test() {
+    let asset = |name: &str| Asset {
+        name: name.to_string(),
+        browser_download_url: format!("https://example.com/{}", name),
+        ..Default::default()
+    };
+    let release = Release {
+        html_url: "https://example.com/release".to_string(),
+        tag_name: "v0.2.0".to_string(),
+        body: Some("notes".to_string()),
+        assets: vec![
+            asset("OscWardrobe_0.2.0_x64_en-US.msi"),
+            asset("OscWardrobe_0.2.0_x64-setup.exe"),
+            asset("OscWardrobe_0.2.0_aarch64.dmg"),
+            asset("OscWardrobe_0.2.0_amd64.AppImage"),
+        ],
+        ..Default::default()
+    };
+    let info = UpdateInfo::from(release);
+    assert_eq!(info.version, "0.2.0", "v prefix is removed");
+    assert_eq!(info.notes, "notes");
+    let expected = if cfg!(target_os = "windows") {
+        "OscWardrobe_0.2.0_x64-setup.exe"
+    } else if cfg!(target_os = "macos") {
+        "OscWardrobe_0.2.0_aarch64.dmg"
+    } else {
+        "OscWardrobe_0.2.0_amd64.AppImage"
+    };
+    assert_eq!(
+        info.installer_url,
+        Some(format!("https://example.com/{}", expected))
+    );
+}
+
+/// `update_cache.json` in AppData: the last response of the releases endpoint.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UpdateCache {
+    /// endpoint the cache is for
+    pub url: String,
+    pub etag: Option<String>,
+    /// last successful response body
+    pub body: Option<String>,
+    /// RFC 3339
+    pub checked_at: Option<String>,
+    /// RFC 3339; no request until then (`Retry-After` or rate limit reset)
+    pub retry_after: Option<String>,
 }
 
+impl UpdateCache {
+    /// Missing or broken file is an empty cache.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Forgets everything if the endpoint changed.
+    fn for_url(&mut self, url: &str) {
+        if self.url != url {
+            *self = UpdateCache {
+                url: url.to_string(),
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Should not request until this time.
+    fn wait_until(&self, min_interval: chrono::Duration) -> Option<DateTime<Utc>> {
+        let retry_after = self.retry_after.as_deref().and_then(parse_time);
+        let next_check = self
+            .checked_at
+            .as_deref()
+            .and_then(parse_time)
+            .map(|t| t + min_interval);
+        retry_after.max(next_check)
+    }
+
+    fn releases(&self) -> Result<Vec<Release>, CheckError> {
+        match &self.body {
+            Some(body) => serde_json::from_str(body).map_err(|e| CheckError::Request(e.into())),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s).ok().map(|t| t.to_utc())
+}
+
+/// `Retry-After` (seconds or HTTP date), or `x-ratelimit-reset` (unix time)
+fn retry_time(
+    retry_after: Option<&str>,
+    ratelimit_reset: Option<u64>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    if let Some(retry_after) = retry_after {
+        if let Ok(seconds) = retry_after.trim().parse::<i64>() {
+            return Some(now + chrono::Duration::seconds(seconds));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc2822(retry_after) {
+            return Some(time.to_utc());
+        }
+    }
+    ratelimit_reset.and_then(|reset| DateTime::from_timestamp(reset as i64, 0))
+}
+
+#[test]
+fn retry_time_test() {
+    let now = parse_time("2025-01-01T00:00:00Z").unwrap();
+    assert_eq!(
+        retry_time(Some("120"), None, now),
+        parse_time("2025-01-01T00:02:00Z")
+    );
+    assert_eq!(
+        retry_time(Some("Wed, 01 Jan 2025 01:00:00 GMT"), Some(0), now),
+        parse_time("2025-01-01T01:00:00Z")
+    );
+    assert_eq!(
+        retry_time(None, Some(1735693200), now),
+        parse_time("2025-01-01T01:00:00Z")
+    );
+    assert_eq!(retry_time(Some("soon"), None, now), None);
+}
+
+#[test]
+fn update_cache_test() {
+    let now = parse_time("2025-01-01T00:00:00Z").unwrap();
+    let hour = chrono::Duration::hours(1);
+    let mut cache = UpdateCache {
+        url: "https://example.com/releases".to_string(),
+        etag: Some("\"abc\"".to_string()),
+        checked_at: Some(now.to_rfc3339()),
+        ..Default::default()
+    };
+    assert_eq!(cache.wait_until(hour), Some(now + hour));
+    cache.retry_after = Some((now + hour * 2).to_rfc3339());
+    assert_eq!(cache.wait_until(hour), Some(now + hour * 2), "later one");
+
+    cache.for_url("https://example.com/releases");
+    assert!(cache.etag.is_some(), "same endpoint");
+    cache.for_url("https://example.com/other");
+    assert_eq!(cache.etag, None, "endpoint changed");
+    assert_eq!(cache.wait_until(hour), None);
+}
+
+#[derive(Debug)]
+pub enum CheckError {
+    /// 403 or 429; `reset` is `x-ratelimit-reset` (unix time) if any
+    RateLimited {
+        reset: Option<u64>,
+    },
+    /// other non-success status
+    Status(u16),
+    /// could not connect
+    Offline(Box<dyn std::error::Error + Send + Sync>),
+    Request(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl CheckError {
+    /// expected while offline or rate limited, not worth an `error!`
+    pub fn is_quiet(&self) -> bool {
+        matches!(
+            self,
+            CheckError::RateLimited { .. } | CheckError::Offline(_)
+        )
+    }
+}
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckError::RateLimited { reset: Some(reset) } => {
+                write!(f, "rate limited until {}", reset)
+            }
+            CheckError::RateLimited { reset: None } => write!(f, "rate limited"),
+            CheckError::Status(status) => write!(f, "unexpected status: {}", status),
+            CheckError::Offline(e) => write!(f, "offline: {}", e),
+            CheckError::Request(e) => write!(f, "request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+impl From<nyquest::Error> for CheckError {
+    fn from(e: nyquest::Error) -> Self {
+        CheckError::Request(e.into())
+    }
+}
+
+/// Only failures to reach the host are [`CheckError::Offline`], so that a bad `endpoint` or a
+/// TLS failure is reported.
+fn request_error(e: nyquest::Error) -> CheckError {
+    use std::io::ErrorKind::*;
+    let offline = match &e {
+        nyquest::Error::RequestTimeout => true,
+        nyquest::Error::Io(io) => {
+            // backends report DNS failures as `Other`
+            let message = io.to_string().to_lowercase();
+            matches!(
+                io.kind(),
+                ConnectionRefused
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | NotConnected
+                    | TimedOut
+                    | HostUnreachable
+                    | NetworkUnreachable
+                    | NetworkDown
+                    | AddrNotAvailable
+            ) || message.contains("resolve")
+                || message.contains("no such host")
+                || message.contains("name or service not known")
+        }
+        _ => false,
+    };
+    if offline {
+        CheckError::Offline(e.into())
+    } else {
+        CheckError::Request(e.into())
+    }
+}
+
+fn header(resp: &nyquest::r#async::Response, name: &str) -> Option<String> {
+    resp.get_header(name).ok()?.into_iter().next()
+}
+
+/// Fetches `settings.releases_url()` unless `cache` is fresh or the endpoint asked to wait.
+/// `Ok(None)` if there is nothing new to show, including channel `off`.
+/// `cache` is updated and should be saved by the caller.
 pub async fn check_for_updates(
     current_version: Version,
-) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let repo = "called-d/osc-wardrobe";
+    settings: &UpdateSettings,
+    cache: &mut UpdateCache,
+    now: DateTime<Utc>,
+) -> Result<Option<UpdateInfo>, CheckError> {
+    if settings.channel == UpdateChannel::Off {
+        info!("update check is off");
+        return Ok(None);
+    }
+    let url = settings.releases_url();
+    cache.for_url(&url);
+    let target = |releases: Vec<Release>| {
+        get_target_release(current_version.clone(), releases, settings.channel)
+            .map(UpdateInfo::from)
+    };
+    let min_interval = chrono::Duration::minutes(settings.min_check_interval_minutes as i64);
+    if let Some(wait_until) = cache.wait_until(min_interval) {
+        if now < wait_until {
+            info!("skip update check until {}, use cache", wait_until);
+            return Ok(target(cache.releases()?));
+        }
+    }
+
     let ua = format!("called-d_osc-wardrobe/{}", current_version);
-    let url = format!("https://api.github.com/repos/{}/releases", repo);
     info!("get release info: {:?}", url);
     let client = nyquest::client::ClientBuilder::default()
         .with_header("Accept", "application/vnd.github+json")
         .user_agent(ua)
         .build_async()
         .await?;
-    let resp = client.request(Request::get(url)).await?;
+    let mut request = Request::get(url);
+    if let (Some(etag), Some(_)) = (&cache.etag, &cache.body) {
+        request = request.with_header("If-None-Match", etag.clone());
+    }
+    let resp = client.request(request).await.map_err(request_error)?;
 
-    match resp.with_successful_status() {
-        Ok(resp) => Ok(
-            get_target_release(current_version, resp.json::<Vec<Release>>().await?)
-                .map(|r| r.html_url),
-        ),
-        Err(e) => Err(e.into()),
+    match resp.status().code() {
+        200..=299 => {
+            let etag = header(&resp, "etag");
+            let body = resp.text().await?;
+            let releases =
+                serde_json::from_str(&body).map_err(|e| CheckError::Request(e.into()))?;
+            cache.etag = etag;
+            cache.body = Some(body);
+            cache.checked_at = Some(now.to_rfc3339());
+            cache.retry_after = None;
+            Ok(target(releases))
+        }
+        304 => {
+            info!("releases not modified");
+            cache.checked_at = Some(now.to_rfc3339());
+            cache.retry_after = None;
+            Ok(target(cache.releases()?))
+        }
+        status @ (403 | 429) => {
+            let reset = header(&resp, "x-ratelimit-reset").and_then(|v| v.parse().ok());
+            let retry_after = retry_time(header(&resp, "retry-after").as_deref(), reset, now)
+                .unwrap_or(now + min_interval);
+            warn!(
+                "update check is rate limited ({}), retry after {}",
+                status, retry_after
+            );
+            cache.retry_after = Some(retry_after.to_rfc3339());
+            Err(CheckError::RateLimited { reset })
+        }
+        status => Err(CheckError::Status(status)),
     }
 }
+
+/// `pending.json` in the staging directory: a downloaded installer to run on next start.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Clone, Debug)]
+pub struct Staged {
+    pub version: String,
+    pub path: std::path::PathBuf,
+    /// set when the installer is started, so it runs only once
+    #[serde(default)]
+    pub launched: bool,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `<hex>  <name>` lines of `sha256sum`, or a single `<hex>` for `<name>.sha256`
+fn parse_checksum(text: &str, file_name: &str) -> Option<String> {
+    let is_hex = |s: &str| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    if let [hex] = lines.as_slice() {
+        if is_hex(hex) {
+            return Some(hex.to_ascii_lowercase());
+        }
+    }
+    lines.iter().find_map(|line| {
+        let (hex, name) = line.split_once(char::is_whitespace)?;
+        (is_hex(hex) && name.trim_start().trim_start_matches('*') == file_name)
+            .then(|| hex.to_ascii_lowercase())
+    })
+}
+
+#[test]
+fn parse_checksum_test() {
+    let a = "a".repeat(64);
+    let b = "B".repeat(64);
+    let sums = format!("{}  OscWardrobe_setup.exe\n{} *OscWardrobe.msi\n", a, b);
+    assert_eq!(
+        parse_checksum(&sums, "OscWardrobe_setup.exe"),
+        Some(a.clone())
+    );
+    assert_eq!(
+        parse_checksum(&sums, "OscWardrobe.msi"),
+        Some("b".repeat(64)),
+        "binary mode, lowercase"
+    );
+    assert_eq!(parse_checksum(&sums, "other.dmg"), None);
+    assert_eq!(parse_checksum(&format!("{}\n", a), "x"), Some(a), "single");
+    assert_eq!(parse_checksum("not a hash", "x"), None);
+}
+
+/// checksum file published with the release, for `installer`
+fn checksum_asset<'a>(assets: &'a [Asset], installer: &Asset) -> Option<&'a Asset> {
+    let own = format!("{}.sha256", installer.name);
+    assets.iter().find(|a| a.name == own).or_else(|| {
+        assets
+            .iter()
+            .find(|a| a.name == "SHA256SUMS" || a.name == "SHA256SUMS.txt")
+    })
+}
+
+/// Downloads the installer of `info`, checks it against the checksum published in the release
+/// and stages it in `dir` to be run on next start.
+///
+/// The checksum comes from the same release, so it only catches a corrupted download, not a
+/// tampered release.
+pub async fn stage_update(
+    current_version: &Version,
+    info: &UpdateInfo,
+    dir: &Path,
+) -> Result<Staged, Box<dyn std::error::Error + Send + Sync>> {
+    let installer = info
+        .assets
+        .iter()
+        .find(|a| Some(&a.browser_download_url) == info.installer_url.as_ref())
+        .ok_or("no installer for this platform")?;
+    let checksum = checksum_asset(&info.assets, installer).ok_or("no checksum in the release")?;
+
+    let client = nyquest::client::ClientBuilder::default()
+        .user_agent(format!("called-d_osc-wardrobe/{}", current_version))
+        .build_async()
+        .await?;
+    info!("download checksum: {}", checksum.browser_download_url);
+    let sums = client
+        .request(Request::get(checksum.browser_download_url.clone()))
+        .await?
+        .with_successful_status()?
+        .text()
+        .await?;
+    let expected = parse_checksum(&sums, &installer.name)
+        .ok_or_else(|| format!("no checksum for {}", installer.name))?;
+
+    info!("download installer: {}", installer.browser_download_url);
+    let bytes = client
+        .request(Request::get(installer.browser_download_url.clone()))
+        .await?
+        .with_successful_status()?
+        .bytes()
+        .await?;
+    let actual = sha256_hex(&bytes);
+    if actual != expected {
+        return Err(format!("checksum mismatch: expected {}, got {}", expected, actual).into());
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(&installer.name);
+    std::fs::write(&path, &bytes)?;
+    let staged = Staged {
+        version: info.version.clone(),
+        path,
+        launched: false,
+    };
+    std::fs::write(dir.join("pending.json"), serde_json::to_string(&staged)?)?;
+    info!("staged update {}: {:?}", staged.version, staged.path);
+    Ok(staged)
+}
+
+/// Staged update newer than `current_version`, marked as launched so a broken installer does
+/// not run on every start. Otherwise everything in `dir` is removed: the installer of an applied
+/// update, and of one launched before which is not retried.
+pub fn take_staged(dir: &Path, current_version: &Version) -> Option<Staged> {
+    let pending = dir.join("pending.json");
+    let staged = std::fs::read_to_string(&pending)
+        .ok()
+        .and_then(|s| serde_json::from_str::<Staged>(&s).ok());
+    if let Some(staged) = staged.filter(|staged| {
+        !staged.launched
+            && parse_tag(&staged.version).is_some_and(|v| v > *current_version)
+            && staged.path.is_file()
+    }) {
+        let launched = Staged {
+            launched: true,
+            ..staged.clone()
+        };
+        match serde_json::to_string(&launched) {
+            Ok(json) if std::fs::write(&pending, json).is_ok() => return Some(staged),
+            // better not to install than to install on every start
+            _ => warn!("could not mark update {} as launched", staged.version),
+        }
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return None;
+    };
+    for entry in entries.flatten() {
+        info!("remove staged update file: {:?}", entry.path());
+        let _ = std::fs::remove_file(entry.path());
+    }
+    None
+}
+
+#[test]
+fn take_staged_test() {
+    let dir = std::env::temp_dir().join(format!("osc-wardrobe-staged-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let installer = dir.join("OscWardrobe_setup.exe");
+    let stage = || {
+        std::fs::write(&installer, b"installer").unwrap();
+        let staged = Staged {
+            version: "v1.1.0".to_string(),
+            path: installer.clone(),
+            launched: false,
+        };
+        std::fs::write(
+            dir.join("pending.json"),
+            serde_json::to_string(&staged).unwrap(),
+        )
+        .unwrap();
+        staged
+    };
+    let v1_0 = Version::parse("1.0.0").unwrap();
+    let v1_1 = Version::parse("1.1.0").unwrap();
+
+    let staged = stage();
+    assert_eq!(take_staged(&dir, &v1_0), Some(staged));
+    assert!(installer.is_file(), "kept for the installer");
+    assert_eq!(take_staged(&dir, &v1_0), None, "runs once");
+    assert_eq!(
+        std::fs::read_dir(&dir).unwrap().count(),
+        0,
+        "a failed install is cleaned up"
+    );
+
+    stage();
+    assert_eq!(take_staged(&dir, &v1_1), None, "applied");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    std::fs::write(&installer, b"left over").unwrap();
+    assert_eq!(take_staged(&dir, &v1_1), None);
+    assert!(!installer.exists(), "without pending.json");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Starts the installer detached. The app should exit right after.
+pub fn launch_installer(path: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("msi"))
+        {
+            let mut c = std::process::Command::new("msiexec");
+            c.arg("/i").arg(path).arg("/passive");
+            c
+        } else {
+            // NSIS installer of Tauri, passive mode
+            let mut c = std::process::Command::new(path);
+            c.arg("/P");
+            c
+        }
+    } else if cfg!(target_os = "macos") {
+        let mut c = std::process::Command::new("open");
+        c.arg(path);
+        c
+    } else {
+        let mut c = std::process::Command::new("xdg-open");
+        c.arg(path);
+        c
+    };
+    command.spawn().map(|_| ())
+}
@@ -0,0 +1,265 @@
+//! `check_for_updates` against a local stand-in of the GitHub releases API.
+
+use crate::settings::{UpdateChannel, UpdateSettings};
+use crate::update::{check_for_updates, CheckError, UpdateCache, UpdateInfo};
+use semver::Version;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+const RELEASES: &str = r#"[
+  {
+    "html_url": "https://example.com/releases/v0.3.0-rc.1",
+    "tag_name": "v0.3.0-rc.1",
+    "prerelease": true,
+    "draft": false,
+    "assets": []
+  },
+  {
+    "html_url": "https://example.com/releases/v0.2.0",
+    "tag_name": "v0.2.0",
+    "body": "- new feature",
+    "published_at": "2025-01-01T00:00:00Z",
+    "prerelease": false,
+    "draft": false,
+    "assets": []
+  },
+  {
+    "html_url": "https://example.com/releases/v0.4.0",
+    "tag_name": "v0.4.0",
+    "prerelease": false,
+    "draft": true,
+    "assets": []
+  }
+]"#;
+
+/// nothing listens here
+const DEAD_URL: &str = "http://127.0.0.1:9/releases";
+
+/// Serves one request with the canned response.
+/// Returns the endpoint URL and the request head (lowercase).
+fn serve_once(status: &str, headers: &[&str], body: &str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("http://{}/releases", listener.local_addr().unwrap());
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+        status,
+        body.len(),
+        headers.iter().map(|h| format!("{}\r\n", h)).collect::<String>(),
+        body
+    );
+    let handle = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).is_ok() && line != "\r\n" && !line.is_empty() {
+            head.push_str(&line.to_lowercase());
+            line.clear();
+        }
+        stream.write_all(response.as_bytes()).unwrap();
+        head
+    });
+    (url, handle)
+}
+
+fn now() -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+        .unwrap()
+        .to_utc()
+}
+
+fn check(
+    current: &str,
+    channel: UpdateChannel,
+    endpoint: &str,
+) -> Result<Option<UpdateInfo>, CheckError> {
+    check_with_cache(
+        current,
+        channel,
+        endpoint,
+        &mut UpdateCache::default(),
+        now(),
+    )
+}
+
+fn check_with_cache(
+    current: &str,
+    channel: UpdateChannel,
+    endpoint: &str,
+    cache: &mut UpdateCache,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Option<UpdateInfo>, CheckError> {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(nyquest_preset::register);
+    let settings = UpdateSettings {
+        channel,
+        endpoint: Some(endpoint.to_string()),
+        ..Default::default()
+    };
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(check_for_updates(
+            Version::parse(current).unwrap(),
+            &settings,
+            cache,
+            now,
+        ))
+}
+
+#[test]
+fn stable_channel() {
+    let (url, _) = serve_once("200 OK", &[], RELEASES);
+    let info = check("0.1.0", UpdateChannel::Stable, &url)
+        .expect("check")
+        .expect("update");
+    assert_eq!(info.version, "0.2.0");
+    assert_eq!(info.notes, "- new feature");
+    assert_eq!(info.published_at.as_deref(), Some("2025-01-01T00:00:00Z"));
+}
+
+#[test]
+fn prerelease_channel() {
+    let (url, _) = serve_once("200 OK", &[], RELEASES);
+    let info = check("0.1.0", UpdateChannel::Prerelease, &url)
+        .expect("check")
+        .expect("update");
+    assert_eq!(info.version, "0.3.0-rc.1");
+    assert!(info.prerelease);
+}
+
+#[test]
+fn up_to_date() {
+    let (url, _) = serve_once("200 OK", &[], RELEASES);
+    assert!(matches!(
+        check("0.2.0", UpdateChannel::Stable, &url),
+        Ok(None)
+    ));
+}
+
+#[test]
+fn off_channel_does_not_request() {
+    assert!(matches!(
+        check("0.1.0", UpdateChannel::Off, DEAD_URL),
+        Ok(None)
+    ));
+}
+
+#[test]
+fn rate_limited() {
+    let (url, _) = serve_once(
+        "403 Forbidden",
+        &["X-RateLimit-Remaining: 0", "X-RateLimit-Reset: 1735689600"],
+        r#"{"message": "API rate limit exceeded"}"#,
+    );
+    assert!(matches!(
+        check("0.1.0", UpdateChannel::Stable, &url),
+        Err(CheckError::RateLimited {
+            reset: Some(1735689600)
+        })
+    ));
+}
+
+#[test]
+fn not_modified() {
+    let (url, _) = serve_once("304 Not Modified", &[], "");
+    assert!(matches!(
+        check("0.1.0", UpdateChannel::Stable, &url),
+        Ok(None)
+    ));
+}
+
+#[test]
+fn server_error() {
+    let (url, _) = serve_once("500 Internal Server Error", &[], "");
+    assert!(matches!(
+        check("0.1.0", UpdateChannel::Stable, &url),
+        Err(CheckError::Status(500))
+    ));
+}
+
+#[test]
+fn offline_is_quiet() {
+    let result = check("0.1.0", UpdateChannel::Stable, DEAD_URL);
+    assert!(matches!(result, Err(CheckError::Offline(_))));
+    assert!(result.unwrap_err().is_quiet());
+}
+
+#[test]
+fn bad_endpoint_is_not_offline() {
+    let result = check("0.1.0", UpdateChannel::Stable, "http://[::1/releases");
+    assert!(
+        matches!(result, Err(CheckError::Request(_))),
+        "{:?}",
+        result
+    );
+    assert!(!result.unwrap_err().is_quiet());
+}
+
+#[test]
+fn etag_and_not_modified() {
+    let mut cache = UpdateCache::default();
+    let (url, _) = serve_once("200 OK", &["ETag: \"v1\""], RELEASES);
+    let first = check_with_cache("0.1.0", UpdateChannel::Stable, &url, &mut cache, now())
+        .expect("first check");
+    assert_eq!(cache.etag.as_deref(), Some("\"v1\""));
+
+    // same port is not guaranteed, so point the cache to the new server
+    let (url, request) = serve_once("304 Not Modified", &[], "");
+    cache.url = url.clone();
+    let later = now() + chrono::Duration::hours(2);
+    let second = check_with_cache("0.1.0", UpdateChannel::Stable, &url, &mut cache, later)
+        .expect("second check");
+    assert!(request
+        .join()
+        .unwrap()
+        .contains("if-none-match: \"v1\"\r\n"));
+    assert_eq!(first, second, "cached releases are used");
+    assert_eq!(cache.checked_at, Some(later.to_rfc3339()));
+}
+
+#[test]
+fn min_check_interval() {
+    let mut cache = UpdateCache::default();
+    let (url, _) = serve_once("200 OK", &[], RELEASES);
+    check_with_cache("0.1.0", UpdateChannel::Stable, &url, &mut cache, now()).expect("check");
+
+    // server is gone, but the cache is fresh
+    let info = check_with_cache(
+        "0.1.0",
+        UpdateChannel::Stable,
+        &url,
+        &mut cache,
+        now() + chrono::Duration::minutes(10),
+    )
+    .expect("cached")
+    .expect("update");
+    assert_eq!(info.version, "0.2.0");
+}
+
+#[test]
+fn retry_after() {
+    let mut cache = UpdateCache::default();
+    let (url, _) = serve_once("429 Too Many Requests", &["Retry-After: 7200"], "");
+    assert!(matches!(
+        check_with_cache("0.1.0", UpdateChannel::Stable, &url, &mut cache, now()),
+        Err(CheckError::RateLimited { .. })
+    ));
+    let retry_after = (now() + chrono::Duration::hours(2)).to_rfc3339();
+    assert_eq!(cache.retry_after, Some(retry_after));
+
+    // no request within an hour after, nothing cached to show
+    assert!(matches!(
+        check_with_cache(
+            "0.1.0",
+            UpdateChannel::Stable,
+            &url,
+            &mut cache,
+            now() + chrono::Duration::hours(1),
+        ),
+        Ok(None)
+    ));
+}
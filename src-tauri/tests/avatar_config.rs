@@ -0,0 +1,63 @@
+//! `avatar_config` against fixture files laid out like VRChat's OSC folder.
+
+use osc_wardrobe_lib::avatar_config::{read_avatar, read_config, read_dir, ParameterAddress};
+use std::path::Path;
+
+fn fixtures() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vrchat_osc"))
+}
+
+#[test]
+fn read_config_with_bom() {
+    let config = read_config(
+        &fixtures().join("usr_00000000-1111-2222-3333-444444444444/Avatars/avtr_casual.json"),
+    )
+    .expect("read");
+    assert_eq!(config.id, "avtr_casual");
+    assert_eq!(config.name, "Casual");
+    assert_eq!(config.parameters.len(), 2);
+    assert_eq!(
+        config.parameters[0].input,
+        Some(ParameterAddress {
+            address: "/avatar/parameters/Outfit".to_string(),
+            r#type: "Int".to_string(),
+        })
+    );
+    assert_eq!(config.parameters[1].input, None, "read-only parameter");
+}
+
+#[test]
+fn read_dir_of_users() {
+    let configs = read_dir(fixtures());
+    assert_eq!(
+        configs.keys().collect::<Vec<_>>(),
+        vec!["avtr_casual", "avtr_formal"],
+        "broken file and folders other than usr_* are skipped"
+    );
+    assert_eq!(configs["avtr_formal"].name, "Formal");
+}
+
+#[test]
+fn read_one_avatar() {
+    assert_eq!(
+        read_avatar(fixtures(), "avtr_formal").map(|c| c.name),
+        Some("Formal".to_string())
+    );
+    assert_eq!(read_avatar(fixtures(), "avtr_broken"), None);
+    assert_eq!(
+        read_avatar(fixtures(), "avtr_ignored"),
+        None,
+        "not a usr_* folder"
+    );
+    assert_eq!(read_avatar(fixtures(), "avtr_missing"), None);
+    assert_eq!(
+        read_avatar(fixtures(), "avtr_../../other/Avatars/avtr_ignored"),
+        None,
+        "path in ID"
+    );
+}
+
+#[test]
+fn read_missing_dir() {
+    assert!(read_dir(&fixtures().join("missing")).is_empty());
+}